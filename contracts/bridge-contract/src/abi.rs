@@ -0,0 +1,252 @@
+use crate::*;
+
+use ethabi::{
+    ethereum_types::{H160, U256},
+    param_type::Reader,
+    short_signature, ParamType, Token,
+};
+use evm::{invalid, parse_address, parse_quantity, EvmTxError};
+use near_sdk::{near, serde_json::{self, Value}};
+use std::io;
+
+/// Contract call encoded by the bridge, as an alternative to raw `data`.
+///
+/// `signature` is the Solidity signature, e.g. `transfer(address,uint256)`. Each argument is
+/// JSON: strings for addresses, bytes (0x-prefixed hex) and numbers (decimal or 0x-hex, `-`
+/// for negative ints), plain numbers for small ints, booleans, and arrays for arrays and tuples.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EvmCall {
+    pub signature: String,
+    pub args: Vec<Value>,
+}
+
+/// Arguments are stored as their JSON text, borsh has no JSON values.
+impl BorshSerialize for EvmCall {
+    fn serialize<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        BorshSerialize::serialize(&(&self.signature, serde_json::to_string(&self.args)?), writer)
+    }
+}
+
+impl BorshDeserialize for EvmCall {
+    fn deserialize_reader<R: io::Read>(reader: &mut R) -> io::Result<Self> {
+        let (signature, args) = <(String, String)>::deserialize_reader(reader)?;
+        Ok(Self { signature, args: serde_json::from_str(&args)? })
+    }
+}
+
+impl EvmCall {
+    /// 4-byte selector followed by the ABI-encoded arguments.
+    pub fn encode(&self) -> Result<Vec<u8>, EvmTxError> {
+        let (name, params) = parse_signature(&self.signature)?;
+        if params.len() != self.args.len() {
+            return Err(invalid(
+                "call.args",
+                format!("{} takes {} arguments, got {}", self.signature, params.len(), self.args.len()),
+            ));
+        }
+
+        let tokens = params
+            .iter()
+            .zip(&self.args)
+            .enumerate()
+            .map(|(i, (param, arg))| tokenize(param, arg, &format!("call.args[{}]", i)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok([short_signature(&name, &params).as_slice(), &ethabi::encode(&tokens)].concat())
+    }
+}
+
+/// Split `name(type,...)` into the function name and its parameter types.
+fn parse_signature(signature: &str) -> Result<(String, Vec<ParamType>), EvmTxError> {
+    let malformed = || invalid("call.signature", format!("expected name(type,...), got {}", signature));
+
+    let (name, params) = signature.split_once('(').ok_or_else(malformed)?;
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$') {
+        return Err(malformed());
+    }
+    if params == ")" {
+        return Ok((name.to_string(), vec![]));
+    }
+
+    match Reader::read(&format!("({}", params)) {
+        Ok(ParamType::Tuple(params)) if params.iter().all(is_valid_param) => Ok((name.to_string(), params)),
+        _ => Err(malformed()),
+    }
+}
+
+/// The reader accepts any width, Solidity only multiples of 8 up to 256 bits and 1 to 32 bytes.
+fn is_valid_param(param: &ParamType) -> bool {
+    match param {
+        ParamType::Uint(bits) | ParamType::Int(bits) => *bits > 0 && *bits <= 256 && bits % 8 == 0,
+        ParamType::FixedBytes(len) => *len > 0 && *len <= 32,
+        ParamType::Array(inner) | ParamType::FixedArray(inner, _) => is_valid_param(inner),
+        ParamType::Tuple(params) => params.iter().all(is_valid_param),
+        ParamType::Address | ParamType::Bool | ParamType::String | ParamType::Bytes => true,
+    }
+}
+
+fn expect_str<'a>(value: &'a Value, field: &str, expected: &str) -> Result<&'a str, EvmTxError> {
+    value.as_str().ok_or_else(|| invalid(field, format!("expected {}, got {}", expected, value)))
+}
+
+fn expect_array<'a>(value: &'a Value, field: &str, len: Option<usize>) -> Result<&'a Vec<Value>, EvmTxError> {
+    let items = value.as_array().ok_or_else(|| invalid(field, format!("expected an array, got {}", value)))?;
+    match len {
+        Some(len) if items.len() != len => {
+            Err(invalid(field, format!("expected {} items, got {}", len, items.len())))
+        }
+        _ => Ok(items),
+    }
+}
+
+fn parse_hex_bytes(value: &Value, field: &str) -> Result<Vec<u8>, EvmTxError> {
+    let hex_str = expect_str(value, field, "0x-prefixed hex")?;
+    hex::decode(hex_str.trim_start_matches("0x")).map_err(|_| invalid(field, format!("{} is not hex", hex_str)))
+}
+
+/// Numbers may be given as JSON numbers only while they're exact, larger ones as strings.
+fn number_str(value: &Value, field: &str) -> Result<String, EvmTxError> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) if n.is_i64() || n.is_u64() => Ok(n.to_string()),
+        _ => Err(invalid(field, format!("expected an integer, got {}", value))),
+    }
+}
+
+fn tokenize(param: &ParamType, value: &Value, field: &str) -> Result<Token, EvmTxError> {
+    let token = match param {
+        ParamType::Address => {
+            Token::Address(H160::from(parse_address(field, expect_str(value, field, "an address")?)?))
+        }
+        ParamType::Bool => {
+            Token::Bool(value.as_bool().ok_or_else(|| invalid(field, format!("expected a boolean, got {}", value)))?)
+        }
+        ParamType::String => Token::String(expect_str(value, field, "a string")?.to_string()),
+        ParamType::Bytes => Token::Bytes(parse_hex_bytes(value, field)?),
+        ParamType::FixedBytes(len) => {
+            let bytes = parse_hex_bytes(value, field)?;
+            if bytes.len() != *len {
+                return Err(invalid(field, format!("expected {} bytes, got {}", len, bytes.len())));
+            }
+            Token::FixedBytes(bytes)
+        }
+        ParamType::Uint(bits) => {
+            let number = parse_quantity(field, &number_str(value, field)?)?;
+            if number.bits() > *bits {
+                return Err(invalid(field, format!("{} doesn't fit in uint{}", number, bits)));
+            }
+            Token::Uint(number)
+        }
+        ParamType::Int(bits) => {
+            let number = number_str(value, field)?;
+            let (negative, magnitude) = match number.strip_prefix('-') {
+                Some(magnitude) => (true, parse_quantity(field, magnitude)?),
+                None => (false, parse_quantity(field, &number)?),
+            };
+            // int{bits} ranges from -2^(bits - 1) to 2^(bits - 1) - 1
+            let bound = U256::one() << (bits - 1);
+            if magnitude > bound || (!negative && magnitude == bound) {
+                return Err(invalid(field, format!("{} doesn't fit in int{}", number, bits)));
+            }
+            // Negative ints are encoded as their two's complement
+            Token::Int(if negative { (!magnitude).overflowing_add(U256::one()).0 } else { magnitude })
+        }
+        ParamType::Array(inner) => {
+            let len = expect_array(value, field, None)?.len();
+            Token::Array(tokenize_all(&vec![inner.as_ref().clone(); len], value, field)?)
+        }
+        ParamType::FixedArray(inner, len) => {
+            Token::FixedArray(tokenize_all(&vec![inner.as_ref().clone(); *len], value, field)?)
+        }
+        ParamType::Tuple(params) => Token::Tuple(tokenize_all(params, value, field)?),
+    };
+    Ok(token)
+}
+
+/// Tokenize the items of the JSON array `value`, which has as many items as `params`.
+fn tokenize_all(params: &[ParamType], value: &Value, field: &str) -> Result<Vec<Token>, EvmTxError> {
+    let items = expect_array(value, field, Some(params.len()))?;
+    params
+        .iter()
+        .zip(items)
+        .enumerate()
+        .map(|(i, (param, item))| tokenize(param, item, &format!("{}[{}]", field, i)))
+        .collect()
+}
+
+#[near]
+impl Contract {
+    /// 0x-prefixed calldata `call` encodes to, exactly as it will be signed in an `EvmTransactionRequest`.
+    #[handle_result]
+    pub fn encode_evm_call(&self, call: EvmCall) -> Result<String, EvmTxError> {
+        Ok(format!("0x{}", hex::encode(call.encode()?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::serde_json::json;
+    use sha3::{Digest, Keccak256};
+
+    fn call(signature: &str, args: Vec<Value>) -> EvmCall {
+        EvmCall { signature: signature.to_string(), args }
+    }
+
+    #[test]
+    fn test_encode_evm_call() {
+        let contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+
+        let transfer = call(
+            "transfer(address,uint256)",
+            vec![json!("0x3535353535353535353535353535353535353535"), json!("0xde0b6b3a7640000")],
+        );
+        assert_eq!(
+            contract.encode_evm_call(transfer.clone()).unwrap(),
+            format!(
+                "0xa9059cbb{:0>64}{:0>64}",
+                "3535353535353535353535353535353535353535", "de0b6b3a7640000"
+            )
+        );
+        let stored: EvmCall = borsh::from_slice(&borsh::to_vec(&transfer).unwrap()).unwrap();
+        assert_eq!(stored, transfer);
+
+        // Examples from the Solidity ABI specification
+        assert_eq!(
+            hex::encode(call("baz(uint32,bool)", vec![json!(69), json!(true)]).encode().unwrap()),
+            format!("cdcd77c0{:0>64}{:0>64}", "45", "1")
+        );
+        assert_eq!(
+            hex::encode(call("sam(bytes,bool,uint256[])", vec![json!("0x64617665"), json!(true), json!([1, 2, 3])]).encode().unwrap()),
+            format!(
+                "a5643bf2{:0>64}{:0>64}{:0>64}{:0>64}{:0<64}{:0>64}{:0>64}{:0>64}{:0>64}",
+                "60", "1", "a0", "4", "64617665", "3", "1", "2", "3"
+            )
+        );
+
+        // Negative ints are two's complement, tuples are arrays
+        let encoded = call("f(int8,(bool,bytes2))", vec![json!("-1"), json!([false, "0xbeef"])]).encode().unwrap();
+        assert_eq!(&encoded[..4], &Keccak256::digest(b"f(int8,(bool,bytes2))")[..4]);
+        assert_eq!(hex::encode(&encoded[4..]), format!("{}{:0>64}{:0<64}", "f".repeat(64), "0", "beef"));
+    }
+
+    #[test]
+    fn test_encode_evm_call_errors() {
+        let error = |signature: &str, args: Vec<Value>| call(signature, args).encode().unwrap_err().to_string();
+
+        assert_eq!(error("transfer", vec![]), "Invalid call.signature: expected name(type,...), got transfer");
+        assert_eq!(error("f(uint257)", vec![json!(1)]), "Invalid call.signature: expected name(type,...), got f(uint257)");
+        assert_eq!(error("f(uint8)", vec![]), "Invalid call.args: f(uint8) takes 1 arguments, got 0");
+        assert_eq!(error("f(uint8)", vec![json!(256)]), "Invalid call.args[0]: 256 doesn't fit in uint8");
+        assert_eq!(error("f(int8)", vec![json!(-129)]), "Invalid call.args[0]: -129 doesn't fit in int8");
+        assert_eq!(error("f(int8)", vec![json!(128)]), "Invalid call.args[0]: 128 doesn't fit in int8");
+        assert_eq!(error("f(uint256[2])", vec![json!([1])]), "Invalid call.args[0]: expected 2 items, got 1");
+        assert_eq!(
+            error("f((address,bool))", vec![json!(["0x35", true])]),
+            "Invalid call.args[0][0]: expected 20 bytes of hex"
+        );
+        assert!(call("f(int8)", vec![json!(-128)]).encode().is_ok());
+        assert!(call("f()", vec![]).encode().is_ok());
+    }
+}
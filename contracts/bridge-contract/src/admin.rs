@@ -0,0 +1,162 @@
+use crate::*;
+
+use events::BridgeEvent;
+use near_sdk::{env, near, NearToken};
+use signer::SignerVersion;
+
+/// Public entry points that can be paused independently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum EntryPoint {
+    /// `sign_btc`. Retries are covered by the entry point that created the request.
+    SignBtc,
+    SignEvm,
+    SignEd25519,
+    SwapBtcKrnl,
+    /// `record_deposit`, crediting LP pools with proven BTC deposits.
+    RecordDeposit,
+}
+
+impl Contract {
+    pub(crate) fn assert_owner(&self) {
+        if env::predecessor_account_id() != self.owner_id {
+            env::panic_str("Only the owner can call this method");
+        }
+    }
+
+    pub(crate) fn assert_not_paused(&self, entry_point: EntryPoint) {
+        if self.paused_entry_points.contains(&entry_point) {
+            env::panic_str(&format!("{:?} is paused", entry_point));
+        }
+    }
+}
+
+#[near]
+impl Contract {
+    pub fn get_owner_id(&self) -> AccountId {
+        self.owner_id.clone()
+    }
+
+    pub fn set_owner_id(&mut self, owner_id: AccountId) {
+        self.assert_owner();
+
+        BridgeEvent::OwnerChanged {
+            old_owner_id: std::mem::replace(&mut self.owner_id, owner_id.clone()),
+            new_owner_id: owner_id,
+        }
+        .emit();
+    }
+
+    /// Point the bridge at a new MPC deployment.
+    ///
+    /// The new deployment has its own root key, so it is cleared and has to be fetched again with
+    /// `sync_mpc_public_key` before addresses can be derived.
+    pub fn set_signer_account(&mut self, signer_account: AccountId, signer_version: SignerVersion) {
+        self.assert_owner();
+
+        self.mpc_public_key = None;
+        self.signer_version = signer_version;
+        BridgeEvent::SignerAccountChanged {
+            old_signer_account: std::mem::replace(&mut self.signer_account, signer_account.clone()),
+            new_signer_account: signer_account,
+        }
+        .emit();
+        BridgeEvent::SignerVersionChanged { signer_version }.emit();
+    }
+
+    /// Switch the protocol used with `signer_account`, e.g. after the signer is upgraded to v2.
+    pub fn set_signer_version(&mut self, signer_version: SignerVersion) {
+        self.assert_owner();
+
+        self.signer_version = signer_version;
+        BridgeEvent::SignerVersionChanged { signer_version }.emit();
+    }
+
+    pub fn set_signature_deposit(&mut self, deposit: NearToken) {
+        self.assert_owner();
+
+        self.signature_deposit = deposit;
+        BridgeEvent::SignatureDepositChanged { signature_deposit: deposit }.emit();
+    }
+
+    pub fn pause(&mut self, entry_point: EntryPoint) {
+        self.assert_owner_or_admin();
+
+        if !self.paused_entry_points.contains(&entry_point) {
+            self.paused_entry_points.push(entry_point);
+            BridgeEvent::Paused { entry_point, by: env::predecessor_account_id() }.emit();
+        }
+    }
+
+    pub fn unpause(&mut self, entry_point: EntryPoint) {
+        self.assert_owner_or_admin();
+
+        if self.paused_entry_points.contains(&entry_point) {
+            self.paused_entry_points.retain(|paused| *paused != entry_point);
+            BridgeEvent::Unpaused { entry_point, by: env::predecessor_account_id() }.emit();
+        }
+    }
+
+    pub fn get_paused_entry_points(&self) -> Vec<EntryPoint> {
+        self.paused_entry_points.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::get_logs;
+    use sign::DEFAULT_SIGNATURE_DEPOSIT;
+    use test_utils::{btc_tx_request, contract_with_relayer, set_context};
+
+    #[test]
+    fn test_owner_actions() {
+        let owner: AccountId = "alice.near".parse().unwrap();
+        let alice: AccountId = "alice.testnet".parse().unwrap();
+        set_context(&owner, NearToken::from_yoctonear(0), vec![]);
+
+        let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+        assert_eq!(contract.get_owner_id(), owner);
+
+        contract.set_signer_account("v1.signer".parse().unwrap(), SignerVersion::V2);
+        assert_eq!(contract.get_signer_account(), "v1.signer".parse::<AccountId>().unwrap());
+        assert_eq!(contract.get_signer_version(), SignerVersion::V2);
+        assert_eq!(contract.get_mpc_public_key(), None);
+        assert!(get_logs()[0].starts_with(r#"EVENT_JSON:{"standard":"bridge","version":"1.0.0","event":"signer_account_changed""#));
+
+        contract.set_owner_id(alice.clone());
+        assert_eq!(contract.get_owner_id(), alice);
+
+        // The previous owner has lost its rights
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.pause(EntryPoint::SignBtc)
+        }));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_pause() {
+        let owner: AccountId = "alice.near".parse().unwrap();
+        let alice: AccountId = "alice.testnet".parse().unwrap();
+        set_context(&owner, NearToken::from_yoctonear(0), vec![]);
+
+        let mut contract = contract_with_relayer(&alice);
+        contract.pause(EntryPoint::SignBtc);
+        contract.pause(EntryPoint::SignBtc);
+        assert_eq!(contract.get_paused_entry_points(), vec![EntryPoint::SignBtc]);
+        assert_eq!(get_logs().len(), 1);
+
+        set_context(&alice, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.sign_btc(btc_tx_request(&alice), None, None)
+        }));
+        assert!(result.is_err());
+
+        set_context(&owner, NearToken::from_yoctonear(0), vec![]);
+        contract.unpause(EntryPoint::SignBtc);
+        assert!(contract.get_paused_entry_points().is_empty());
+
+        set_context(&alice, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        contract.sign_btc(btc_tx_request(&alice), None, None);
+    }
+}
@@ -0,0 +1,424 @@
+use crate::*;
+
+use events::BridgeEvent;
+use near_sdk::{env, json_types::U128, near, NearToken, PromiseOrValue};
+use roles::Role;
+use sign::refund_deposit;
+
+const DEFAULT_PAGE_LIMIT: u64 = 50;
+const DEFAULT_APPROVAL_TTL_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// Guardians needed to approve a transfer, and how long they have to do it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ApprovalPolicy {
+    pub quorum: u32,
+    pub ttl_ns: u64,
+}
+
+impl Default for ApprovalPolicy {
+    fn default() -> Self {
+        Self {
+            quorum: 1,
+            ttl_ns: DEFAULT_APPROVAL_TTL_NS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum ApprovalStatus {
+    Pending,
+    Approved,
+    Rejected,
+    Expired,
+}
+
+/// Transfer above the approval threshold, held until the guardians decide on it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Approval {
+    pub request_id: RequestId,
+    pub chain: Chain,
+    #[schemars(with = "String")]
+    pub amount: U128,
+    /// Deposit attached by the requester, spent on the signatures once approved and refunded otherwise.
+    #[schemars(with = "String")]
+    pub deposit: NearToken,
+    pub approved_by: Vec<AccountId>,
+    pub status: ApprovalStatus,
+    pub created_at: u64,
+    pub expires_at: u64,
+}
+
+impl Contract {
+    fn assert_guardian(&self) {
+        if !self.has_role_internal(Role::Guardian, &env::predecessor_account_id()) {
+            env::panic_str("Only guardians can call this method");
+        }
+    }
+
+    fn guardian_count(&self) -> u64 {
+        self.role_members
+            .get(&Role::Guardian)
+            .map_or(0, |members| members.len() as u64)
+    }
+
+    /// Approvals of guardians whose role has since been revoked don't count.
+    fn current_approvals(&self, approval: &Approval) -> u32 {
+        approval
+            .approved_by
+            .iter()
+            .filter(|guardian| self.has_role_internal(Role::Guardian, guardian))
+            .count() as u32
+    }
+
+    fn expect_pending_approval(&self, request_id: &RequestId) -> Approval {
+        let approval = self
+            .approvals
+            .get(request_id)
+            .unwrap_or_else(|| env::panic_str("Request is not awaiting approval"));
+        if approval.status != ApprovalStatus::Pending {
+            env::panic_str(&format!("Approval is already {:?}", approval.status));
+        }
+        approval.clone()
+    }
+
+    /// Whether the value of requests on `chain` needs to be known, to count it against the
+    /// outflow caps or compare it with the approval threshold.
+    pub(crate) fn tracks_value(&self, chain: Chain, lp_account_id: Option<&AccountId>) -> bool {
+        self.is_outflow_capped(chain, lp_account_id) || self.approval_thresholds.contains_key(&chain)
+    }
+
+    /// Whether a transfer of `amount` needs the guardians. Resubmitting an approved transfer doesn't.
+    pub(crate) fn requires_approval(&self, request_id: &RequestId, chain: Chain, amount: u128) -> bool {
+        let above_threshold = self
+            .approval_thresholds
+            .get(&chain)
+            .is_some_and(|threshold| amount > *threshold);
+        let approved = self
+            .approvals
+            .get(request_id)
+            .is_some_and(|approval| approval.status == ApprovalStatus::Approved);
+        above_threshold && !approved
+    }
+
+    /// Hold a registered request and its `deposit` until the guardians reach the quorum.
+    pub(crate) fn queue_approval(&mut self, request_id: &RequestId, chain: Chain, amount: u128, deposit: NearToken) {
+        let now = env::block_timestamp();
+        let expires_at = now.saturating_add(self.approval_policy.ttl_ns);
+
+        self.mark_request_awaiting_approval(request_id);
+        self.approvals.insert(request_id.clone(), Approval {
+            request_id: request_id.clone(),
+            chain,
+            amount: U128(amount),
+            deposit,
+            approved_by: vec![],
+            status: ApprovalStatus::Pending,
+            created_at: now,
+            expires_at,
+        });
+        self.approval_queue.insert(request_id.clone());
+
+        BridgeEvent::ApprovalRequested { request_id: request_id.clone(), chain, amount: U128(amount), expires_at }.emit();
+    }
+
+    /// Requests waiting for the guardians, or that they rejected, can't be retried.
+    pub(crate) fn assert_approved(&self, request_id: &RequestId) {
+        if self
+            .approvals
+            .get(request_id)
+            .is_some_and(|approval| approval.status != ApprovalStatus::Approved)
+        {
+            env::panic_str("Request was not approved by the guardians");
+        }
+    }
+
+    /// Close an approval and take the request out of the queue.
+    fn resolve_approval(&mut self, request_id: &RequestId, status: ApprovalStatus) -> Approval {
+        let approval = self.approvals.get_mut(request_id).expect("Unknown approval");
+        approval.status = status;
+        let approval = approval.clone();
+        self.approval_queue.remove(request_id);
+        approval
+    }
+
+    /// Send an approved request to the signer, paying its signatures from the held `deposit`.
+    fn dispatch_approved(&mut self, request_id: RequestId, deposit: NearToken) -> PromiseOrValue<Option<String>> {
+        let request = self.expect_request(&request_id);
+        let (requester, chain) = (request.requester.clone(), request.chain);
+        let indexes = request.missing_signatures();
+
+        let required = self.signature_deposit.saturating_mul(indexes.len() as u128);
+        if deposit < required {
+            refund_deposit(&requester, deposit);
+            self.mark_request_failed(
+                &request_id,
+                format!("Held deposit {} is below the {} now required for signatures", deposit, required),
+            );
+            return PromiseOrValue::Value(None);
+        }
+
+        let leftover_deposit = deposit.saturating_sub(required);
+        self.mark_request_pending(&request_id);
+        BridgeEvent::SignRequested { request_id: request_id.clone(), requester, chain, indexes: indexes.clone() }.emit();
+        match chain {
+            Chain::Bitcoin => self.dispatch_btc_signatures(request_id, indexes, leftover_deposit).into(),
+            Chain::Evm => self.dispatch_evm_signature(request_id, leftover_deposit).into(),
+            Chain::Solana | Chain::Near => env::panic_str("Only BTC and EVM transfers need approval"),
+        }
+    }
+}
+
+#[near]
+impl Contract {
+    pub fn set_approval_policy(&mut self, policy: ApprovalPolicy) {
+        self.assert_owner();
+        if policy.quorum == 0 {
+            env::panic_str("Quorum must be at least one guardian");
+        }
+
+        BridgeEvent::ApprovalPolicyChanged { quorum: policy.quorum, ttl_ns: policy.ttl_ns }.emit();
+        self.approval_policy = policy;
+    }
+
+    /// Require guardian approval for transfers on `chain` above `threshold`, in the chain's smallest
+    /// unit. A `None` threshold lets every transfer through.
+    pub fn set_approval_threshold(&mut self, chain: Chain, threshold: Option<U128>) {
+        self.assert_owner();
+
+        match threshold {
+            Some(threshold) => {
+                if self.guardian_count() < self.approval_policy.quorum as u64 {
+                    env::panic_str("Not enough guardians to reach the quorum");
+                }
+                self.approval_thresholds.insert(chain, threshold.0);
+            }
+            None => {
+                self.approval_thresholds.remove(&chain);
+            }
+        }
+        BridgeEvent::ApprovalThresholdChanged { chain, threshold }.emit();
+    }
+
+    /// Approve a queued transfer. The approval reaching the quorum sends it to the signer, so
+    /// approving waits for the entry point that created the transfer to be unpaused.
+    pub fn approve(&mut self, request_id: RequestId) -> PromiseOrValue<Option<String>> {
+        self.assert_guardian();
+        self.assert_not_paused(self.expect_request(&request_id).entry_point);
+
+        let guardian = env::predecessor_account_id();
+        let approval = self.expect_pending_approval(&request_id);
+        if env::block_timestamp() >= approval.expires_at {
+            env::panic_str("Approval has expired");
+        }
+        if approval.approved_by.contains(&guardian) {
+            env::panic_str("Already approved by this guardian");
+        }
+
+        let initial_storage_usage = env::storage_usage();
+        let approval = self.approvals.get_mut(&request_id).expect("Unknown approval");
+        approval.approved_by.push(guardian.clone());
+        let approval = approval.clone();
+        let approvals = self.current_approvals(&approval);
+        let requester = self.expect_request(&request_id).requester.clone();
+        self.record_storage_usage(&requester, initial_storage_usage);
+        BridgeEvent::ApprovalGiven { request_id: request_id.clone(), guardian, approvals }.emit();
+
+        if approvals < self.approval_policy.quorum {
+            return PromiseOrValue::Value(None);
+        }
+        let approval = self.resolve_approval(&request_id, ApprovalStatus::Approved);
+        BridgeEvent::QuorumReached { request_id: request_id.clone() }.emit();
+        self.dispatch_approved(request_id, approval.deposit)
+    }
+
+    /// Veto a queued transfer. Its deposit is refunded, and the liquidity of a swap payout goes
+    /// back to the pool.
+    pub fn reject(&mut self, request_id: RequestId) {
+        self.assert_guardian();
+
+        let guardian = env::predecessor_account_id();
+        self.expect_pending_approval(&request_id);
+        let requester = self.expect_request(&request_id).requester.clone();
+
+        let initial_storage_usage = env::storage_usage();
+        let approval = self.resolve_approval(&request_id, ApprovalStatus::Rejected);
+        self.mark_request_rejected(&request_id, format!("Rejected by guardian {}", guardian));
+        self.release_held_liquidity(&request_id);
+        self.record_storage_usage(&requester, initial_storage_usage);
+        refund_deposit(&requester, approval.deposit);
+
+        BridgeEvent::ApprovalRejected { request_id, guardian }.emit();
+    }
+
+    /// Expire a transfer the guardians didn't decide on in time and refund its deposit.
+    pub fn expire_approval(&mut self, request_id: RequestId) {
+        let approval = self.expect_pending_approval(&request_id);
+        if env::block_timestamp() < approval.expires_at {
+            env::panic_str("Approval has not expired yet");
+        }
+
+        let requester = self.expect_request(&request_id).requester.clone();
+        let initial_storage_usage = env::storage_usage();
+        self.resolve_approval(&request_id, ApprovalStatus::Expired);
+        self.mark_request_expired(&request_id);
+        self.record_storage_usage(&requester, initial_storage_usage);
+        refund_deposit(&requester, approval.deposit);
+
+        BridgeEvent::ApprovalExpired { request_id }.emit();
+    }
+
+    pub fn get_approval_policy(&self) -> ApprovalPolicy {
+        self.approval_policy.clone()
+    }
+
+    pub fn get_approval_threshold(&self, chain: Chain) -> Option<U128> {
+        self.approval_thresholds.get(&chain).map(|threshold| U128(*threshold))
+    }
+
+    pub fn get_approval(&self, request_id: RequestId) -> Option<Approval> {
+        self.approvals.get(&request_id).cloned()
+    }
+
+    /// Transfers waiting for the guardians, oldest first.
+    pub fn get_pending_approvals(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<Approval> {
+        self.approval_queue
+            .iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(DEFAULT_PAGE_LIMIT) as usize)
+            .filter_map(|request_id| self.approvals.get(request_id).cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use admin::EntryPoint;
+    use near_sdk::{test_utils::get_logs, test_utils::VMContextBuilder, testing_env};
+    use registry::RequestStatus;
+    use sign::DEFAULT_SIGNATURE_DEPOSIT;
+    use test_utils::{btc_tx_request, contract_with_relayer, set_context};
+
+    fn guarded_contract(owner: &AccountId, guardians: &[AccountId]) -> Contract {
+        set_context(owner, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        let mut contract = contract_with_relayer(owner);
+        for guardian in guardians {
+            contract.grant_role(Role::Guardian, guardian.clone());
+        }
+        contract.set_approval_policy(ApprovalPolicy { quorum: 2, ttl_ns: 1_000 });
+        contract.set_approval_threshold(Chain::Bitcoin, Some(U128(100_000)));
+        contract
+    }
+
+    #[test]
+    fn test_quorum_approval() {
+        let owner: AccountId = "alice.near".parse().unwrap();
+        let guardians: Vec<AccountId> = vec!["g1.near".parse().unwrap(), "g2.near".parse().unwrap()];
+        let mut contract = guarded_contract(&owner, &guardians);
+
+        set_context(&owner, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        assert!(matches!(contract.sign_btc(btc_tx_request(&owner), None, None), PromiseOrValue::Value(None)));
+        let pending = contract.get_pending_approvals(None, None);
+        assert_eq!(pending.len(), 1);
+        let request_id = pending[0].request_id.clone();
+        assert_eq!(contract.get_request(request_id.clone()).unwrap().status, RequestStatus::AwaitingApproval);
+
+        // Only guardians approve, each once
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| contract.approve(request_id.clone())));
+        assert!(result.is_err());
+        set_context(&guardians[0], NearToken::from_yoctonear(0), vec![]);
+        assert!(matches!(contract.approve(request_id.clone()), PromiseOrValue::Value(None)));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| contract.approve(request_id.clone())));
+        assert!(result.is_err());
+
+        set_context(&guardians[1], NearToken::from_yoctonear(0), vec![]);
+        assert!(matches!(contract.approve(request_id.clone()), PromiseOrValue::Promise(_)));
+        assert!(get_logs().iter().any(|log| log.contains("\"event\":\"quorum_reached\"")));
+        assert_eq!(contract.get_request(request_id.clone()).unwrap().status, RequestStatus::Pending);
+        assert_eq!(contract.get_approval(request_id).unwrap().status, ApprovalStatus::Approved);
+        assert!(contract.get_pending_approvals(None, None).is_empty());
+    }
+
+    #[test]
+    fn test_quorum_counts_current_guardians_while_unpaused() {
+        let owner: AccountId = "alice.near".parse().unwrap();
+        let guardians: Vec<AccountId> =
+            vec!["g1.near".parse().unwrap(), "g2.near".parse().unwrap(), "g3.near".parse().unwrap()];
+        let mut contract = guarded_contract(&owner, &guardians);
+
+        set_context(&owner, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        contract.sign_btc(btc_tx_request(&owner), None, None);
+        let request_id = contract.get_pending_approvals(None, None)[0].request_id.clone();
+
+        set_context(&guardians[0], NearToken::from_yoctonear(0), vec![]);
+        contract.approve(request_id.clone());
+
+        // The approval of a revoked guardian no longer counts towards the quorum
+        set_context(&owner, NearToken::from_yoctonear(0), vec![]);
+        contract.revoke_role(Role::Guardian, guardians[0].clone());
+        set_context(&guardians[1], NearToken::from_yoctonear(0), vec![]);
+        assert!(matches!(contract.approve(request_id.clone()), PromiseOrValue::Value(None)));
+        assert_eq!(contract.get_request(request_id.clone()).unwrap().status, RequestStatus::AwaitingApproval);
+
+        // Approvals don't get past a paused sign_btc
+        set_context(&owner, NearToken::from_yoctonear(0), vec![]);
+        contract.pause(EntryPoint::SignBtc);
+        set_context(&guardians[2], NearToken::from_yoctonear(0), vec![]);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| contract.approve(request_id.clone())));
+        assert!(result.is_err());
+
+        set_context(&owner, NearToken::from_yoctonear(0), vec![]);
+        contract.unpause(EntryPoint::SignBtc);
+        set_context(&guardians[2], NearToken::from_yoctonear(0), vec![]);
+        assert!(matches!(contract.approve(request_id.clone()), PromiseOrValue::Promise(_)));
+        assert_eq!(contract.get_request(request_id).unwrap().status, RequestStatus::Pending);
+    }
+
+    #[test]
+    fn test_rejection_and_expiry() {
+        let owner: AccountId = "alice.near".parse().unwrap();
+        let guardians: Vec<AccountId> = vec!["g1.near".parse().unwrap(), "g2.near".parse().unwrap()];
+        let mut contract = guarded_contract(&owner, &guardians);
+
+        set_context(&owner, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        contract.sign_btc(btc_tx_request(&owner), None, None);
+        let request_id = contract.get_pending_approvals(None, None)[0].request_id.clone();
+
+        set_context(&guardians[0], NearToken::from_yoctonear(0), vec![]);
+        contract.reject(request_id.clone());
+        assert_eq!(contract.get_request(request_id.clone()).unwrap().status, RequestStatus::Rejected);
+
+        // A rejected transfer can't be submitted again
+        set_context(&owner, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.sign_btc(btc_tx_request(&owner), None, None)
+        }));
+        assert!(result.is_err());
+
+        let mut tx_request = btc_tx_request(&owner);
+        tx_request.outputs[0].value += 1;
+        contract.sign_btc(tx_request, None, None);
+        let request_id = contract.get_pending_approvals(None, None)[0].request_id.clone();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.expire_approval(request_id.clone())
+        }));
+        assert!(result.is_err());
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(owner.clone())
+            .block_timestamp(1_000)
+            .build());
+        contract.expire_approval(request_id.clone());
+        assert_eq!(contract.get_request(request_id.clone()).unwrap().status, RequestStatus::Expired);
+
+        // Expired transfers can't be retried without approval
+        set_context(&owner, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.retry_btc_signature(request_id.clone(), vec![0])
+        }));
+        assert!(result.is_err());
+    }
+}
@@ -16,6 +16,7 @@ use schemars::JsonSchema;
 use sha2::{Digest, Sha256};
 use signer::SignResult;
 use std::error::Error;
+use verify::{normalize_s, parse_public_key_hex, verify_signature, SignatureError};
 
 /// Length of a valid P2WPKH witness program script_pubkey.
 /// P2WPKH script_pubkey: 0x00 0x14 (20-byte-hash)
@@ -159,53 +160,87 @@ impl Contract {
         PreparedBitcoinTransaction { tx, sighashes }
     }
 
+    #[handle_result]
     pub fn finalize_btc_tx(
         &self,
-        tx: BitcoinTransaction,
+        prepared_bitcoin_transaction: PreparedBitcoinTransaction,
         signatures: Vec<SignResult>,
         signer_public_key: String,
-    ) -> String {
-        // Decode the public key from hex
-        let public_key = hex::decode(signer_public_key).expect("Invalid public key hex");
-
-        // Create witnesses for each input
-        let mut final_tx = tx;
-        for (i, signature) in signatures.iter().enumerate() {
-            // Extract R and S as 32-byte integers
-            let r_bytes = extract_32_byte_scalar_from_hex(&signature.big_r.affine_point);
-            let s_bytes = extract_32_byte_scalar_from_hex(&signature.s.scalar);
-
-            // Normalize R and S for DER
-            let r = normalize_der_int(r_bytes);
-            let s = normalize_der_int(s_bytes);
-
-            // Construct the DER-encoded signature
-            let total_len = 2 + r.len() + 2 + s.len(); // 2 bytes overhead per integer
-            let mut der_signature = Vec::with_capacity(6 + r.len() + s.len());
-            der_signature.push(0x30); // DER sequence
-            der_signature.push(total_len as u8);
-            der_signature.push(0x02); // integer for R
-            der_signature.push(r.len() as u8);
-            der_signature.extend_from_slice(&r);
-            der_signature.push(0x02); // integer for S
-            der_signature.push(s.len() as u8);
-            der_signature.extend_from_slice(&s);
-            
-            // Append SIGHASH_ALL (0x01)
-            der_signature.push(0x01);
-
-            // Create the witness with DER-encoded signature and public key
-            let witness = Witness::from_slice(&[&der_signature, &public_key]);
-
-            // Assign witness to this input
-            final_tx.input[i].witness = witness;
+    ) -> Result<String, SignatureError> {
+        let PreparedBitcoinTransaction { tx, sighashes } = prepared_bitcoin_transaction;
+
+        if signatures.len() != sighashes.len() {
+            return Err(SignatureError::CountMismatch {
+                expected: sighashes.len() as u64,
+                actual: signatures.len() as u64,
+            });
         }
 
-        // Serialize and return hex
-        let serialized = final_tx.serialize();
-        hex::encode(serialized)
+        // Standardness rules reject high-S signatures
+        let signatures = signatures
+            .into_iter()
+            .map(normalize_s)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Every input must be signed by the key placed in its witness
+        let expected_public_key = parse_public_key_hex(&signer_public_key)?;
+        for (sighash, signature) in sighashes.iter().zip(signatures.iter()) {
+            verify_signature(sighash, signature, &expected_public_key)?;
+        }
+
+        Ok(build_signed_btc_tx(tx, &signatures, &signer_public_key))
+    }
+}
+
+/// Place each signature and the signer's public key in the witness of its input.
+///
+/// Signatures must already be normalized and verified, see `finalize_btc_tx`.
+pub(crate) fn build_signed_btc_tx(
+    tx: BitcoinTransaction,
+    signatures: &[SignResult],
+    signer_public_key: &str,
+) -> String {
+    // Decode the public key from hex
+    let public_key = hex::decode(signer_public_key).expect("Invalid public key hex");
+
+    // Create witnesses for each input
+    let mut final_tx = tx;
+    for (i, signature) in signatures.iter().enumerate() {
+        // Extract R and S as 32-byte integers
+        let r_bytes = extract_32_byte_scalar_from_hex(&signature.big_r.affine_point);
+        let s_bytes = extract_32_byte_scalar_from_hex(&signature.s.scalar);
+
+        // Normalize R and S for DER
+        let r = normalize_der_int(r_bytes);
+        let s = normalize_der_int(s_bytes);
+
+        // Construct the DER-encoded signature
+        let total_len = 2 + r.len() + 2 + s.len(); // 2 bytes overhead per integer
+        let mut der_signature = Vec::with_capacity(6 + r.len() + s.len());
+        der_signature.push(0x30); // DER sequence
+        der_signature.push(total_len as u8);
+        der_signature.push(0x02); // integer for R
+        der_signature.push(r.len() as u8);
+        der_signature.extend_from_slice(&r);
+        der_signature.push(0x02); // integer for S
+        der_signature.push(s.len() as u8);
+        der_signature.extend_from_slice(&s);
+        
+        // Append SIGHASH_ALL (0x01)
+        der_signature.push(0x01);
+
+        // Create the witness with DER-encoded signature and public key
+        let witness = Witness::from_slice(&[&der_signature, &public_key]);
+
+        // Assign witness to this input
+        final_tx.input[i].witness = witness;
     }
+
+    // Serialize and return hex
+    let serialized = final_tx.serialize();
+    hex::encode(serialized)
 }
+
 fn normalize_der_int(mut val: Vec<u8>) -> Vec<u8> {
     // Remove leading zeros
     while val.len() > 1 && val[0] == 0x00 {
@@ -251,6 +286,94 @@ fn extract_32_byte_scalar_from_hex(hex_str: &str) -> Vec<u8> {
     }
 }
 
+/// Txid and outputs of a raw transaction, as read by `parse_btc_tx`.
+#[derive(Debug, Clone)]
+pub struct ParsedBitcoinTransaction {
+    /// Hex in the reversed byte order explorers display.
+    pub txid: String,
+    pub outputs: Vec<BtcOutput>,
+}
+
+/// Reads a raw transaction front to back, failing once it runs out of bytes.
+struct TxReader<'a> {
+    tx: &'a [u8],
+    position: usize,
+}
+
+impl<'a> TxReader<'a> {
+    fn take(&mut self, len: u64) -> Result<&'a [u8], String> {
+        let end = usize::try_from(len)
+            .ok()
+            .and_then(|len| self.position.checked_add(len))
+            .filter(|end| *end <= self.tx.len())
+            .ok_or("Transaction is truncated")?;
+        let bytes = &self.tx[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn compact_size(&mut self) -> Result<u64, String> {
+        let bytes = match self.take(1)?[0] {
+            0xfd => self.take(2)?,
+            0xfe => self.take(4)?,
+            0xff => self.take(8)?,
+            len => return Ok(len as u64),
+        };
+        let mut value = [0u8; 8];
+        value[..bytes.len()].copy_from_slice(bytes);
+        Ok(u64::from_le_bytes(value))
+    }
+}
+
+/// Parse a raw legacy or segwit transaction. Its txid hashes it without the witnesses.
+pub(crate) fn parse_btc_tx(tx: &[u8]) -> Result<ParsedBitcoinTransaction, String> {
+    let mut reader = TxReader { tx, position: 0 };
+    let version = reader.take(4)?;
+    let is_segwit = tx.get(4..6) == Some(&[0x00, 0x01][..]);
+    if is_segwit {
+        reader.take(2)?;
+    }
+
+    let body_start = reader.position;
+    let input_count = reader.compact_size()?;
+    if input_count == 0 {
+        return Err("Transaction has no inputs".to_string());
+    }
+    for _ in 0..input_count {
+        // Previous outpoint, script_sig and sequence
+        reader.take(36)?;
+        let script_len = reader.compact_size()?;
+        reader.take(script_len)?;
+        reader.take(4)?;
+    }
+
+    let output_count = reader.compact_size()?;
+    let mut outputs = Vec::new();
+    for _ in 0..output_count {
+        let value = u64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+        let script_len = reader.compact_size()?;
+        outputs.push(BtcOutput { value, script_pubkey: hex::encode(reader.take(script_len)?) });
+    }
+    let body = &tx[body_start..reader.position];
+
+    if is_segwit {
+        for _ in 0..input_count {
+            for _ in 0..reader.compact_size()? {
+                let item_len = reader.compact_size()?;
+                reader.take(item_len)?;
+            }
+        }
+    }
+    let lock_time = reader.take(4)?;
+    if reader.position != tx.len() {
+        return Err("Transaction has trailing bytes".to_string());
+    }
+
+    let mut txid = double_sha256(&[version, body, lock_time].concat());
+    txid.reverse();
+    Ok(ParsedBitcoinTransaction { txid: hex::encode(txid), outputs })
+}
+
 /// Check if the given script_pubkey is a valid P2WPKH witness program.
 ///
 /// A valid P2WPKH witness program starts with 0x00, 0x14 followed by a 20-byte hash.
@@ -316,11 +439,53 @@ mod tests {
 
         let public_key = "02b12224ecec8184dbff10316a889ebee9f7871bd6de358c5323fbecce9d84fd24".to_string();
 
-        let final_tx = contract.finalize_btc_tx(prepared_bitcoin_transaction.tx, vec![signature], public_key);
+        let final_tx = contract.finalize_btc_tx(prepared_bitcoin_transaction.clone(), vec![signature.clone()], public_key.clone()).unwrap();
 
         assert_eq!(
             final_tx,
             "020000000001017053bc4f12b37901db04da385e1db5bd7371a8953dbb78f1990f1216a4e0d3b90100000000ffffffff02b004000000000000160014d3ae5a5de66aa44e7d5723b74e590340b3212f46368b0600000000001600140d7d0223d302b4e8ef37050b5200b1c3306ae7ab02483045022100e123dac9ea85ff349a301bd6591657f1ed8a0d349f226080d624022284f4d1930220689983efbbf85df34a99507df24077ba85c92fcb54146d554f55b60a1626a816012102b12224ecec8184dbff10316a889ebee9f7871bd6de358c5323fbecce9d84fd2400000000"
         );
+
+        // A high-S signature from the signer is normalized to the same transaction
+        let high_s_signature = SignResult {
+            big_r: signature.big_r.clone(),
+            s: SerializableScalar {
+                scalar: "97667C104407A20CB566AF820DBF884434E5AD1B5B3432E6707CA882BA0F992B".to_string(),
+            },
+            recovery_id: 1,
+        };
+        assert_eq!(
+            contract.finalize_btc_tx(prepared_bitcoin_transaction.clone(), vec![high_s_signature], public_key.clone()).unwrap(),
+            final_tx
+        );
+
+        // The same signature must not be accepted for a different witness key
+        let other_public_key = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798".to_string();
+        assert!(matches!(
+            contract.finalize_btc_tx(prepared_bitcoin_transaction, vec![signature], other_public_key),
+            Err(SignatureError::PublicKeyMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_btc_tx() {
+        // Coinbase of the genesis block, whose txid is also the block's merkle root
+        let genesis = "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+        let parsed = parse_btc_tx(&hex::decode(genesis).unwrap()).unwrap();
+        assert_eq!(parsed.txid, "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b");
+        assert_eq!(parsed.outputs.len(), 1);
+        assert_eq!(parsed.outputs[0].value, 5_000_000_000);
+
+        // Witnesses don't change the txid
+        let signed = "020000000001017053bc4f12b37901db04da385e1db5bd7371a8953dbb78f1990f1216a4e0d3b90100000000ffffffff02b004000000000000160014d3ae5a5de66aa44e7d5723b74e590340b3212f46368b0600000000001600140d7d0223d302b4e8ef37050b5200b1c3306ae7ab02483045022100e123dac9ea85ff349a301bd6591657f1ed8a0d349f226080d624022284f4d1930220689983efbbf85df34a99507df24077ba85c92fcb54146d554f55b60a1626a816012102b12224ecec8184dbff10316a889ebee9f7871bd6de358c5323fbecce9d84fd2400000000";
+        let stripped = "02000000017053bc4f12b37901db04da385e1db5bd7371a8953dbb78f1990f1216a4e0d3b90100000000ffffffff02b004000000000000160014d3ae5a5de66aa44e7d5723b74e590340b3212f46368b0600000000001600140d7d0223d302b4e8ef37050b5200b1c3306ae7ab00000000";
+        let parsed = parse_btc_tx(&hex::decode(signed).unwrap()).unwrap();
+        assert_eq!(parsed.txid, parse_btc_tx(&hex::decode(stripped).unwrap()).unwrap().txid);
+        assert_eq!(parsed.outputs[1].value, 428854);
+        assert_eq!(parsed.outputs[1].script_pubkey, "00140d7d0223d302b4e8ef37050b5200b1c3306ae7ab");
+
+        let bytes = hex::decode(signed).unwrap();
+        assert_eq!(parse_btc_tx(&bytes[..bytes.len() - 1]).unwrap_err(), "Transaction is truncated");
+        assert_eq!(parse_btc_tx(&[&bytes[..], &[0]].concat()).unwrap_err(), "Transaction has trailing bytes");
     }
 }
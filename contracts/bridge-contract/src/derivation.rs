@@ -0,0 +1,331 @@
+use crate::*;
+
+use k256::{
+    elliptic_curve::{ops::Reduce, sec1::ToEncodedPoint},
+    AffinePoint, ProjectivePoint, Scalar, U256,
+};
+use near_sdk::{env, near, serde::{Deserialize, Serialize}, CurveType, Gas, Promise};
+use schemars::JsonSchema;
+use sha3::{Digest, Keccak256, Sha3_256};
+use signer::{ext_signer, ext_signer_v2, SignerVersion, SECP256K1_DOMAIN_ID};
+
+/// Prefix used by the NEAR MPC network when deriving child keys.
+const EPSILON_DERIVATION_PREFIX: &str = "near-mpc-recovery v0.1.0 epsilon derivation:";
+
+const PUBLIC_KEY_GAS: Gas = Gas::from_tgas(10);
+const PUBLIC_KEY_CALLBACK_GAS: Gas = Gas::from_tgas(5);
+
+/// Character set used by bech32 to encode 5-bit groups.
+const BECH32_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32_GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde", rename_all = "lowercase")]
+pub enum BtcNetwork {
+    Mainnet,
+    Testnet,
+    Regtest,
+}
+
+impl BtcNetwork {
+    /// Human readable part of the network's segwit addresses.
+    pub fn bech32_hrp(&self) -> &'static str {
+        match self {
+            BtcNetwork::Mainnet => "bc",
+            BtcNetwork::Testnet => "tb",
+            BtcNetwork::Regtest => "bcrt",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DerivedBtcAddress {
+    pub address: String,
+    pub script_pubkey: String,
+    pub public_key: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DerivedEvmAddress {
+    pub address: String,
+    pub public_key: String,
+}
+
+/// Tweak added to the root key for `predecessor` and `path`, as computed by the MPC network.
+pub fn derive_epsilon(predecessor: &AccountId, path: &str) -> Scalar {
+    let derivation_path = format!("{}{},{}", EPSILON_DERIVATION_PREFIX, predecessor, path);
+    let hash: [u8; 32] = Sha3_256::digest(derivation_path.as_bytes()).into();
+    <Scalar as Reduce<U256>>::reduce_bytes(&hash.into())
+}
+
+/// Child public key: `root + epsilon * G`.
+pub fn derive_public_key(root: &AffinePoint, predecessor: &AccountId, path: &str) -> AffinePoint {
+    let epsilon = derive_epsilon(predecessor, path);
+    (ProjectivePoint::GENERATOR * epsilon + ProjectivePoint::from(*root)).to_affine()
+}
+
+/// Decode a NEAR `secp256k1:` public key into a curve point.
+pub fn affine_point_from_near_public_key(public_key: &PublicKey) -> AffinePoint {
+    if public_key.curve_type() != CurveType::SECP256K1 {
+        env::panic_str("MPC public key must be a secp256k1 key");
+    }
+
+    // NEAR stores the curve type followed by the uncompressed point without its 0x04 tag.
+    let mut sec1 = vec![0x04];
+    sec1.extend_from_slice(&public_key.as_bytes()[1..]);
+
+    k256::PublicKey::from_sec1_bytes(&sec1)
+        .unwrap_or_else(|_| env::panic_str("Invalid MPC public key"))
+        .as_affine()
+        .to_owned()
+}
+
+pub fn compressed_public_key(point: &AffinePoint) -> Vec<u8> {
+    point.to_encoded_point(true).as_bytes().to_vec()
+}
+
+/// P2WPKH witness program: `0x0014{hash160(compressed_public_key)}`.
+pub fn p2wpkh_script_pubkey(point: &AffinePoint) -> Vec<u8> {
+    let sha256 = env::sha256(&compressed_public_key(point));
+    let hash160 = env::ripemd160_array(&sha256);
+
+    let mut script = vec![0x00, 0x14];
+    script.extend_from_slice(&hash160);
+    script
+}
+
+/// Segwit v0 address for the P2WPKH output of `point`.
+pub fn p2wpkh_address(point: &AffinePoint, network: BtcNetwork) -> String {
+    let script = p2wpkh_script_pubkey(point);
+    encode_segwit_v0_address(network.bech32_hrp(), &script[2..])
+}
+
+/// Last 20 bytes of the keccak hash of the uncompressed point.
+pub fn evm_address_bytes(point: &AffinePoint) -> [u8; 20] {
+    let uncompressed = point.to_encoded_point(false);
+    let hash = Keccak256::digest(&uncompressed.as_bytes()[1..]);
+
+    let mut address = [0u8; 20];
+    address.copy_from_slice(&hash[12..]);
+    address
+}
+
+/// EIP-55 mixed-case checksum encoding of an EVM address.
+pub fn to_checksum_address(address: &[u8; 20]) -> String {
+    let lower = hex::encode(address);
+    let hash = Keccak256::digest(lower.as_bytes());
+
+    let checksummed: String = lower
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble = (hash[i / 2] >> (if i % 2 == 0 { 4 } else { 0 })) & 0x0f;
+            if c.is_ascii_alphabetic() && nibble >= 8 {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect();
+
+    format!("0x{}", checksummed)
+}
+
+fn encode_segwit_v0_address(hrp: &str, program: &[u8]) -> String {
+    let mut data = vec![0u8];
+    data.extend(convert_bits_8_to_5(program));
+
+    let mut values = bech32_hrp_expand(hrp);
+    values.extend_from_slice(&data);
+    values.extend_from_slice(&[0u8; 6]);
+    let polymod = bech32_polymod(&values) ^ 1;
+
+    let mut address = format!("{}1", hrp);
+    for value in data {
+        address.push(BECH32_CHARSET[value as usize] as char);
+    }
+    for i in 0..6 {
+        let value = (polymod >> (5 * (5 - i))) & 31;
+        address.push(BECH32_CHARSET[value as usize] as char);
+    }
+    address
+}
+
+fn bech32_hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut expanded: Vec<u8> = hrp.bytes().map(|b| b >> 5).collect();
+    expanded.push(0);
+    expanded.extend(hrp.bytes().map(|b| b & 31));
+    expanded
+}
+
+fn bech32_polymod(values: &[u8]) -> u32 {
+    let mut checksum = 1u32;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x1ffffff) << 5) ^ *value as u32;
+        for (i, generator) in BECH32_GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+fn convert_bits_8_to_5(data: &[u8]) -> Vec<u8> {
+    let mut accumulator = 0u32;
+    let mut bits = 0u32;
+    let mut converted = Vec::with_capacity(data.len() * 8 / 5 + 1);
+
+    for byte in data {
+        accumulator = (accumulator << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            converted.push(((accumulator >> bits) & 31) as u8);
+        }
+    }
+    if bits > 0 {
+        converted.push(((accumulator << (5 - bits)) & 31) as u8);
+    }
+    converted
+}
+
+impl Contract {
+    /// Public key the signer will use for `path` when called by this contract.
+    pub fn derived_public_key(&self, path: &str) -> AffinePoint {
+        let mpc_public_key = self
+            .mpc_public_key
+            .as_ref()
+            .unwrap_or_else(|| env::panic_str("MPC public key not synced, call sync_mpc_public_key"));
+
+        derive_public_key(
+            &affine_point_from_near_public_key(mpc_public_key),
+            &env::current_account_id(),
+            path,
+        )
+    }
+}
+
+#[near]
+impl Contract {
+    /// Fetch the root public key from the signer so addresses can be derived locally.
+    pub fn sync_mpc_public_key(&mut self) -> Promise {
+        let public_key = match self.signer_version {
+            SignerVersion::V1 => ext_signer::ext(self.signer_account.clone())
+                .with_static_gas(PUBLIC_KEY_GAS)
+                .public_key(),
+            SignerVersion::V2 => ext_signer_v2::ext(self.signer_account.clone())
+                .with_static_gas(PUBLIC_KEY_GAS)
+                .public_key(Some(SECP256K1_DOMAIN_ID)),
+        };
+
+        public_key.then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(PUBLIC_KEY_CALLBACK_GAS)
+                .sync_mpc_public_key_callback()
+        )
+    }
+
+    #[private]
+    pub fn sync_mpc_public_key_callback(
+        &mut self,
+        #[callback_unwrap] public_key: PublicKey,
+    ) -> PublicKey {
+        affine_point_from_near_public_key(&public_key);
+        self.mpc_public_key = Some(public_key.clone());
+        public_key
+    }
+
+    pub fn get_mpc_public_key(&self) -> Option<PublicKey> {
+        self.mpc_public_key.clone()
+    }
+
+    /// `path` is the full derivation path sent to the signer, e.g. `alice.near/btc/0`.
+    pub fn get_btc_address(&self, path: String, network: BtcNetwork) -> DerivedBtcAddress {
+        let point = self.derived_public_key(&path);
+
+        DerivedBtcAddress {
+            address: p2wpkh_address(&point, network),
+            script_pubkey: hex::encode(p2wpkh_script_pubkey(&point)),
+            public_key: hex::encode(compressed_public_key(&point)),
+        }
+    }
+
+    /// `path` is the full derivation path sent to the signer, e.g. `alice.near/evm/0`.
+    pub fn get_evm_address(&self, path: String) -> DerivedEvmAddress {
+        let point = self.derived_public_key(&path);
+
+        DerivedEvmAddress {
+            address: to_checksum_address(&evm_address_bytes(&point)),
+            public_key: hex::encode(compressed_public_key(&point)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generator() -> AffinePoint {
+        ProjectivePoint::GENERATOR.to_affine()
+    }
+
+    #[test]
+    fn test_addresses_from_public_key() {
+        // Private key 1, whose addresses are well known.
+        let point = generator();
+
+        assert_eq!(
+            hex::encode(compressed_public_key(&point)),
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+        );
+        assert_eq!(p2wpkh_address(&point, BtcNetwork::Mainnet), "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4");
+        assert_eq!(to_checksum_address(&evm_address_bytes(&point)), "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf");
+
+        let btc_test_key = k256::PublicKey::from_sec1_bytes(
+            &hex::decode("02b12224ecec8184dbff10316a889ebee9f7871bd6de358c5323fbecce9d84fd24").unwrap()
+        ).unwrap();
+        assert_eq!(
+            hex::encode(p2wpkh_script_pubkey(btc_test_key.as_affine())),
+            "00140d7d0223d302b4e8ef37050b5200b1c3306ae7ab"
+        );
+        assert_eq!(
+            p2wpkh_address(btc_test_key.as_affine(), BtcNetwork::Testnet),
+            "tb1qp47syg7nq26w3mehq594yq93cvcx4eatrvrtmc"
+        );
+    }
+
+    #[test]
+    fn test_derived_key_matches_tweaked_private_key() {
+        let root_secret = Scalar::from(0x1234_5678_9abc_def0u64);
+        let root = (ProjectivePoint::GENERATOR * root_secret).to_affine();
+        let predecessor: AccountId = "bridge.testnet".parse().unwrap();
+
+        let derived = derive_public_key(&root, &predecessor, "alice.testnet/btc/0");
+        let epsilon = derive_epsilon(&predecessor, "alice.testnet/btc/0");
+        assert_eq!(derived, (ProjectivePoint::GENERATOR * (root_secret + epsilon)).to_affine());
+
+        assert_ne!(derived, derive_public_key(&root, &predecessor, "bob.testnet/btc/0"));
+    }
+
+    #[test]
+    fn test_get_addresses_from_synced_key() {
+        let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+        let root = (ProjectivePoint::GENERATOR * Scalar::from(42u64)).to_affine();
+        let uncompressed = root.to_encoded_point(false);
+        let mut near_key = vec![CurveType::SECP256K1 as u8];
+        near_key.extend_from_slice(&uncompressed.as_bytes()[1..]);
+        contract.sync_mpc_public_key_callback(PublicKey::try_from(near_key).unwrap());
+
+        let derived = derive_public_key(&root, &env::current_account_id(), "alice.testnet");
+        let btc = contract.get_btc_address("alice.testnet".to_string(), BtcNetwork::Testnet);
+        let evm = contract.get_evm_address("alice.testnet".to_string());
+
+        assert_eq!(btc.address, p2wpkh_address(&derived, BtcNetwork::Testnet));
+        assert_eq!(btc.public_key, evm.public_key);
+        assert_eq!(evm.address, to_checksum_address(&evm_address_bytes(&derived)));
+    }
+}
@@ -0,0 +1,231 @@
+use crate::*;
+
+use near_sdk::{env, near, serde::{Deserialize, Serialize}};
+use near_sdk::borsh::{BorshDeserialize, BorshSerialize};
+use schemars::JsonSchema;
+use verify::SignatureError;
+
+const ED25519_SIGNATURE_LEN: usize = 64;
+const ED25519_PUBLIC_KEY_LEN: usize = 32;
+
+/// Versioned Solana messages set the high bit of their first byte.
+const SOLANA_VERSION_PREFIX: u8 = 0x80;
+
+/// `KeyType::ED25519` in NEAR's borsh encoding of public keys and signatures.
+const NEAR_ED25519_KEY_TYPE: u8 = 0;
+
+/// Unsigned transaction for a chain signing with Ed25519, hex-encoded.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde", tag = "chain", rename_all = "snake_case")]
+pub enum Ed25519TransactionRequest {
+    /// Serialized legacy or v0 message, with the fee payer as its only signer.
+    Solana { message: String },
+    /// Borsh-serialized `Transaction`.
+    Near { transaction: String },
+}
+
+impl Ed25519TransactionRequest {
+    pub fn chain(&self) -> Chain {
+        match self {
+            Ed25519TransactionRequest::Solana { .. } => Chain::Solana,
+            Ed25519TransactionRequest::Near { .. } => Chain::Near,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PreparedEd25519Transaction {
+    pub chain: Chain,
+    pub unsigned_tx: Vec<u8>,
+    /// Bytes the signer signs: the Solana message itself, or the sha256 of a NEAR transaction.
+    pub payload: Vec<u8>,
+    /// Key that must have produced the signature, read from the transaction.
+    pub signer_public_key: [u8; 32],
+}
+
+/// Read a Solana compact-u16 length, returning it with the number of bytes it took.
+fn read_compact_u16(bytes: &[u8]) -> Option<(usize, usize)> {
+    let mut value = 0usize;
+    for (i, byte) in bytes.iter().take(3).enumerate() {
+        value |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+/// The fee payer is the first account key, after the optional version byte and the 3-byte header.
+fn solana_fee_payer(message: &[u8]) -> [u8; 32] {
+    let header_start = match message.first() {
+        Some(prefix) if prefix & SOLANA_VERSION_PREFIX != 0 => 1,
+        _ => 0,
+    };
+    let num_required_signatures = message.get(header_start).copied().unwrap_or(0);
+    if num_required_signatures != 1 {
+        env::panic_str("Solana message must have exactly one signer");
+    }
+
+    let keys_start = header_start + 3;
+    let (num_keys, len_size) = read_compact_u16(message.get(keys_start..).unwrap_or_default())
+        .unwrap_or_else(|| env::panic_str("Invalid Solana account keys length"));
+    let payer_start = keys_start + len_size;
+    if num_keys == 0 {
+        env::panic_str("Solana message has no account keys");
+    }
+
+    message
+        .get(payer_start..payer_start + ED25519_PUBLIC_KEY_LEN)
+        .and_then(|key| key.try_into().ok())
+        .unwrap_or_else(|| env::panic_str("Solana message is truncated"))
+}
+
+/// A NEAR transaction starts with the borsh `signer_id` string followed by the signer's public key.
+fn near_signer_public_key(transaction: &[u8]) -> [u8; 32] {
+    let signer_id_len = transaction
+        .get(..4)
+        .map(|len| u32::from_le_bytes(len.try_into().unwrap()) as usize)
+        .unwrap_or_else(|| env::panic_str("NEAR transaction is truncated"));
+    // The length comes from the caller and may not fit in a 32-bit usize once offset
+    let key_type_index = signer_id_len
+        .checked_add(4)
+        .unwrap_or_else(|| env::panic_str("NEAR transaction is truncated"));
+    let key_end = key_type_index
+        .checked_add(1 + ED25519_PUBLIC_KEY_LEN)
+        .unwrap_or_else(|| env::panic_str("NEAR transaction is truncated"));
+
+    if transaction.get(key_type_index) != Some(&NEAR_ED25519_KEY_TYPE) {
+        env::panic_str("NEAR transaction must be signed with an ed25519 key");
+    }
+
+    transaction
+        .get(key_type_index + 1..key_end)
+        .and_then(|key| key.try_into().ok())
+        .unwrap_or_else(|| env::panic_str("NEAR transaction is truncated"))
+}
+
+/// Solana transaction: compact-u16 signature count, the signatures, then the message.
+fn solana_signed_tx(message: &[u8], signature: &[u8; 64]) -> Vec<u8> {
+    [&[1u8][..], signature, message].concat()
+}
+
+/// NEAR `SignedTransaction`: the transaction followed by the borsh `Signature` enum.
+fn near_signed_tx(transaction: &[u8], signature: &[u8; 64]) -> Vec<u8> {
+    [transaction, &[NEAR_ED25519_KEY_TYPE], signature].concat()
+}
+
+#[near]
+impl Contract {
+    pub fn prepare_ed25519_tx(&self, tx_request: Ed25519TransactionRequest) -> PreparedEd25519Transaction {
+        let chain = tx_request.chain();
+
+        match tx_request {
+            Ed25519TransactionRequest::Solana { message } => {
+                let message = hex::decode(message)
+                    .unwrap_or_else(|_| env::panic_str("Invalid Solana message hex"));
+
+                PreparedEd25519Transaction {
+                    chain,
+                    signer_public_key: solana_fee_payer(&message),
+                    payload: message.clone(),
+                    unsigned_tx: message,
+                }
+            }
+            Ed25519TransactionRequest::Near { transaction } => {
+                let transaction = hex::decode(transaction)
+                    .unwrap_or_else(|_| env::panic_str("Invalid NEAR transaction hex"));
+
+                PreparedEd25519Transaction {
+                    chain,
+                    signer_public_key: near_signer_public_key(&transaction),
+                    payload: env::sha256(&transaction),
+                    unsigned_tx: transaction,
+                }
+            }
+        }
+    }
+
+    /// Attach the 64-byte `signature` once it is confirmed to come from the transaction's signer.
+    #[handle_result]
+    pub fn finalize_ed25519_tx(
+        &self,
+        prepared_ed25519_transaction: PreparedEd25519Transaction,
+        signature: Vec<u8>,
+    ) -> Result<String, SignatureError> {
+        let PreparedEd25519Transaction { chain, unsigned_tx, payload, signer_public_key } = prepared_ed25519_transaction;
+
+        let signature: [u8; ED25519_SIGNATURE_LEN] = signature.try_into().map_err(|_| SignatureError::Malformed {
+            reason: "ed25519 signature must be 64 bytes".to_string(),
+        })?;
+        if !env::ed25519_verify(&signature, &payload, &signer_public_key) {
+            return Err(SignatureError::Ed25519VerificationFailed {
+                public_key: hex::encode(signer_public_key),
+            });
+        }
+
+        let tx = match chain {
+            Chain::Solana => solana_signed_tx(&unsigned_tx, &signature),
+            Chain::Near => near_signed_tx(&unsigned_tx, &signature),
+            Chain::Bitcoin | Chain::Evm => env::panic_str("Not an ed25519 chain"),
+        };
+
+        Ok(hex::encode(tx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_utils::{near_tx_request, near_tx_signature};
+
+    const PUBLIC_KEY: &str = "03a107bff3ce10be1d70dd18e74bc09967e4d6309ba50d5f1ddc8664125531b8";
+
+    #[test]
+    fn test_solana_tx() {
+        let message = "0100010303a107bff3ce10be1d70dd18e74bc09967e4d6309ba50d5f1ddc8664125531b807070707070707070707070707070707070707070707070707070707070707070000000000000000000000000000000000000000000000000000000000000000090909090909090909090909090909090909090909090909090909090909090901020200010c02000000e803000000000000";
+        let signature = hex::decode("2349e05f79963abb3030c8d4a245d57543ca23d98d68110acd01a393db881a6b9da79d65ec5c90ae272751c190a6a07fd6849d907f198423b2bbf2dffa509102").unwrap();
+
+        let contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+        let prepared = contract.prepare_ed25519_tx(Ed25519TransactionRequest::Solana { message: message.to_string() });
+        assert_eq!(hex::encode(prepared.signer_public_key), PUBLIC_KEY);
+        assert_eq!(hex::encode(&prepared.payload), message);
+
+        let tx_hex = contract.finalize_ed25519_tx(prepared.clone(), signature.clone()).unwrap();
+        assert_eq!(tx_hex, format!("01{}{}", hex::encode(&signature), message));
+
+        let mut wrong_signature = signature;
+        wrong_signature[0] ^= 1;
+        assert!(matches!(
+            contract.finalize_ed25519_tx(prepared, wrong_signature),
+            Err(SignatureError::Ed25519VerificationFailed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_near_tx() {
+        let Ed25519TransactionRequest::Near { transaction } = near_tx_request() else { unreachable!() };
+        let signature = near_tx_signature();
+
+        let contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+        let prepared = contract.prepare_ed25519_tx(near_tx_request());
+        assert_eq!(hex::encode(prepared.signer_public_key), PUBLIC_KEY);
+        assert_eq!(prepared.payload, env::sha256(&hex::decode(&transaction).unwrap()));
+
+        let tx_hex = contract.finalize_ed25519_tx(prepared.clone(), signature.clone()).unwrap();
+        assert_eq!(tx_hex, format!("{}00{}", transaction, hex::encode(&signature)));
+
+        assert!(matches!(
+            contract.finalize_ed25519_tx(prepared, signature[..63].to_vec()),
+            Err(SignatureError::Malformed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_near_signer_id_length_is_bounded() {
+        // A signer id length close to u32::MAX points past the end of the transaction
+        let transaction = [&u32::MAX.to_le_bytes()[..], &[0u8; 40]].concat();
+        let result = std::panic::catch_unwind(|| near_signer_public_key(&transaction));
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,139 @@
+use crate::*;
+
+use admin::EntryPoint;
+use limits::OutflowCap;
+use lp::{LpStatus, RiskTier};
+use registry::RequestId;
+use near_sdk::{json_types::U128, NearToken};
+use roles::{Role, RolePermissions};
+use signer::SignerVersion;
+
+/// NEP-297 events emitted by the bridge, logged as `EVENT_JSON:{...}`.
+#[near(event_json(standard = "bridge"))]
+pub enum BridgeEvent {
+    #[event_version("1.0.0")]
+    SignRequested { request_id: RequestId, requester: AccountId, chain: Chain, indexes: Vec<u32> },
+
+    #[event_version("1.0.0")]
+    SignatureReceived { request_id: RequestId, index: u32 },
+
+    #[event_version("1.0.0")]
+    SignFailed { request_id: RequestId, error: String },
+
+    #[event_version("1.0.0")]
+    RequestExpired { request_id: RequestId },
+
+    #[event_version("1.0.0")]
+    TxFinalized { request_id: RequestId, chain: Chain, signed_tx: String },
+
+    #[event_version("1.0.0")]
+    SwapAuthorized { requester: AccountId, lp_account_id: AccountId, request_id: RequestId, amount: U128 },
+
+    #[event_version("1.0.0")]
+    SwapRejected { requester: AccountId, reason: String },
+
+    #[event_version("1.0.0")]
+    OwnerChanged { old_owner_id: AccountId, new_owner_id: AccountId },
+
+    #[event_version("1.0.0")]
+    SignerAccountChanged { old_signer_account: AccountId, new_signer_account: AccountId },
+
+    #[event_version("1.0.0")]
+    SignerVersionChanged { signer_version: SignerVersion },
+
+    #[event_version("1.0.0")]
+    SignatureDepositChanged { signature_deposit: NearToken },
+
+    #[event_version("1.0.0")]
+    Paused { entry_point: EntryPoint, by: AccountId },
+
+    #[event_version("1.0.0")]
+    Unpaused { entry_point: EntryPoint, by: AccountId },
+
+    #[event_version("1.0.0")]
+    RoleGranted { role: Role, account_id: AccountId, by: AccountId },
+
+    #[event_version("1.0.0")]
+    RoleRevoked { role: Role, account_id: AccountId, by: AccountId },
+
+    #[event_version("1.0.0")]
+    RolePermissionsChanged { role: Role, permissions: RolePermissions },
+
+    #[event_version("1.0.0")]
+    UpgradeStaged { code_hash: String, deployable_at: u64 },
+
+    #[event_version("1.0.0")]
+    UpgradeCancelled { code_hash: String },
+
+    #[event_version("1.0.0")]
+    UpgradeDeployed { code_hash: String },
+
+    #[event_version("1.0.0")]
+    UpgradeDelayChanged { upgrade_delay_ns: u64 },
+
+    #[event_version("1.0.0")]
+    UpgradeDelayDecreaseStaged { upgrade_delay_ns: u64, effective_at: u64 },
+
+    #[event_version("1.0.0")]
+    LpRegistered { account_id: AccountId, risk_tier: RiskTier },
+
+    #[event_version("1.0.0")]
+    LpUpdated { account_id: AccountId },
+
+    #[event_version("1.0.0")]
+    LpStatusChanged { account_id: AccountId, status: LpStatus },
+
+    #[event_version("1.0.0")]
+    LpRiskTierChanged { account_id: AccountId, risk_tier: RiskTier },
+
+    #[event_version("1.0.0")]
+    LpDeregistered { account_id: AccountId },
+
+    #[event_version("1.0.0")]
+    DepositRecorded { account_id: AccountId, chain: Chain, tx_id: String, index: u32, amount: U128 },
+
+    #[event_version("1.0.0")]
+    DepositRejected { account_id: AccountId, chain: Chain, tx_id: String, index: u32, reason: String },
+
+    #[event_version("1.0.0")]
+    BtcLightClientChanged { light_client: AccountId, confirmations: u64 },
+
+    #[event_version("1.0.0")]
+    PoolDebited { account_id: AccountId, chain: Chain, request_id: RequestId, amount: U128 },
+
+    #[event_version("1.0.0")]
+    PoolDebitReleased { account_id: AccountId, chain: Chain, request_id: RequestId, amount: U128 },
+
+    #[event_version("1.0.0")]
+    FeeScheduleChanged { chain: Chain, bps: u16, min: U128 },
+
+    #[event_version("1.0.0")]
+    FeeCollected { chain: Chain, request_id: RequestId, amount: U128 },
+
+    #[event_version("1.0.0")]
+    TreasuryWithdrawn { chain: Chain, request_id: RequestId, amount: U128 },
+
+    #[event_version("1.0.0")]
+    OutflowCapChanged { chain: Chain, lp_account_id: Option<AccountId>, cap: Option<OutflowCap> },
+
+    #[event_version("1.0.0")]
+    ApprovalPolicyChanged { quorum: u32, ttl_ns: u64 },
+
+    #[event_version("1.0.0")]
+    ApprovalThresholdChanged { chain: Chain, threshold: Option<U128> },
+
+    #[event_version("1.0.0")]
+    ApprovalRequested { request_id: RequestId, chain: Chain, amount: U128, expires_at: u64 },
+
+    #[event_version("1.0.0")]
+    ApprovalGiven { request_id: RequestId, guardian: AccountId, approvals: u32 },
+
+    #[event_version("1.0.0")]
+    QuorumReached { request_id: RequestId },
+
+    #[event_version("1.0.0")]
+    ApprovalRejected { request_id: RequestId, guardian: AccountId },
+
+    #[event_version("1.0.0")]
+    ApprovalExpired { request_id: RequestId },
+}
@@ -1,78 +1,393 @@
 use crate::*;
 
-use near_sdk::{env::keccak256, log, near, serde::{Deserialize, Serialize}};
+use abi::EvmCall;
+use ethabi::ethereum_types::{FromDecStrErr, FromStrRadixErrKind, U256};
+use near_sdk::{env::keccak256, log, near, serde::{Deserialize, Serialize}, FunctionError};
+use near_sdk::borsh::{BorshDeserialize, BorshSerialize};
 use schemars::JsonSchema;
-use omni_transaction::{
-    evm::{evm_transaction::EVMTransaction, types::Signature as OmniSignature, utils::parse_eth_address},
-    transaction_builder::{TransactionBuilder, TxBuilder},
-    types::EVM,
-};
+use omni_transaction::evm::types::{AccessList, Address};
+use rlp::RlpStream;
+use derivation::{evm_address_bytes, to_checksum_address};
 use signer::SignResult;
-use hex;
+use std::fmt;
+use verify::{normalize_s, parse_evm_address_hex, verify_evm_signature, SignatureError};
+
+/// Reasons an `EvmTransactionRequest` can't be turned into a transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde", tag = "kind", rename_all = "snake_case")]
+pub enum EvmTxError {
+    /// `field` is required by the transaction type but empty.
+    MissingField { field: String },
+    /// `field` could not be decoded.
+    InvalidField { field: String, reason: String },
+    /// Legacy transactions predate access lists.
+    AccessListNotSupported,
+    /// A deployment without `to` must carry init code in `data`.
+    MissingInitCode,
+}
+
+impl fmt::Display for EvmTxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvmTxError::MissingField { field } => write!(f, "{} is required", field),
+            EvmTxError::InvalidField { field, reason } => write!(f, "Invalid {}: {}", field, reason),
+            EvmTxError::AccessListNotSupported => write!(f, "Legacy transactions can't have an access list"),
+            EvmTxError::MissingInitCode => write!(f, "Contract deployments need init code in data"),
+        }
+    }
+}
+
+impl FunctionError for EvmTxError {
+    fn panic(&self) -> ! {
+        env::panic_str(&self.to_string())
+    }
+}
+
+pub(crate) fn invalid(field: &str, reason: impl Into<String>) -> EvmTxError {
+    EvmTxError::InvalidField { field: field.to_string(), reason: reason.into() }
+}
+
+/// Parse a decimal or `0x`-prefixed hex quantity of up to 256 bits.
+pub fn parse_quantity(field: &str, quantity: &str) -> Result<U256, EvmTxError> {
+    if quantity.is_empty() {
+        return Err(EvmTxError::MissingField { field: field.to_string() });
+    }
+
+    match quantity.strip_prefix("0x").or_else(|| quantity.strip_prefix("0X")) {
+        Some("") => Err(invalid(field, "no hex digits after 0x")),
+        Some(digits) => U256::from_str_radix(digits, 16).map_err(|e| match e.kind() {
+            FromStrRadixErrKind::InvalidLength => invalid(field, "exceeds 256 bits"),
+            _ => invalid(field, format!("{} is not a hex number", quantity)),
+        }),
+        None => U256::from_dec_str(quantity).map_err(|e| match e {
+            FromDecStrErr::InvalidLength => invalid(field, "exceeds 256 bits"),
+            FromDecStrErr::InvalidCharacter => invalid(field, format!("{} is not a decimal or 0x-prefixed hex number", quantity)),
+        }),
+    }
+}
+
+pub(crate) fn parse_address(field: &str, address: &str) -> Result<Address, EvmTxError> {
+    parse_evm_address_hex(address).map_err(|_| invalid(field, "expected 20 bytes of hex"))
+}
+
+fn parse_bytes32(field: &str, value: &str) -> Result<[u8; 32], EvmTxError> {
+    hex::decode(value.trim_start_matches("0x"))
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .ok_or_else(|| invalid(field, "expected 32 bytes of hex"))
+}
+
+/// Envelope of an EVM transaction, selecting its fee fields, signing hash and `v`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum EvmTxType {
+    /// Untyped transaction priced by `gas_price`, replay-protected by EIP-155.
+    Legacy,
+    /// EIP-2930 type 1 transaction priced by `gas_price`.
+    Eip2930,
+    /// EIP-1559 type 2 transaction priced by `max_fee_per_gas` and `max_priority_fee_per_gas`.
+    #[default]
+    Eip1559,
+}
+
+/// Unsigned transaction built from an `EvmTransactionRequest`, RLP-encoded according to `tx_type`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EvmTransaction {
+    pub tx_type: EvmTxType,
+    pub chain_id: u64,
+    pub nonce: u64,
+    /// `None` deploys `input` as init code.
+    pub to: Option<Address>,
+    #[schemars(with = "String")]
+    pub value: U256,
+    pub input: Vec<u8>,
+    #[schemars(with = "String")]
+    pub gas_limit: U256,
+    /// Only used by legacy and EIP-2930 transactions.
+    #[schemars(with = "String")]
+    pub gas_price: U256,
+    /// Only used by EIP-1559 transactions.
+    #[schemars(with = "String")]
+    pub max_fee_per_gas: U256,
+    /// Only used by EIP-1559 transactions.
+    #[schemars(with = "String")]
+    pub max_priority_fee_per_gas: U256,
+    pub access_list: AccessList,
+}
+
+impl EvmTransaction {
+    fn append_fields(&self, stream: &mut RlpStream) {
+        if self.tx_type != EvmTxType::Legacy {
+            stream.append(&self.chain_id);
+        }
+        stream.append(&self.nonce);
+        match self.tx_type {
+            EvmTxType::Legacy | EvmTxType::Eip2930 => {
+                stream.append(&self.gas_price);
+            }
+            EvmTxType::Eip1559 => {
+                stream.append(&self.max_priority_fee_per_gas);
+                stream.append(&self.max_fee_per_gas);
+            }
+        }
+        stream.append(&self.gas_limit);
+        match &self.to {
+            Some(to) => stream.append(&to.to_vec()),
+            None => stream.append_empty_data(),
+        };
+        stream.append(&self.value);
+        stream.append(&self.input);
+
+        if self.tx_type != EvmTxType::Legacy {
+            stream.begin_unbounded_list();
+            for (address, storage_keys) in &self.access_list {
+                stream.begin_list(2);
+                stream.append(&address.to_vec());
+                stream.begin_unbounded_list();
+                for storage_key in storage_keys {
+                    stream.append(&storage_key.to_vec());
+                }
+                stream.finalize_unbounded_list();
+            }
+            stream.finalize_unbounded_list();
+        }
+    }
+
+    /// Typed transactions are prefixed with their EIP-2718 type byte.
+    fn envelope(&self, payload: &[u8]) -> Vec<u8> {
+        match self.tx_type {
+            EvmTxType::Legacy => payload.to_vec(),
+            EvmTxType::Eip2930 => [&[0x01], payload].concat(),
+            EvmTxType::Eip1559 => [&[0x02], payload].concat(),
+        }
+    }
+
+    /// Payload whose keccak256 is signed. Legacy transactions commit to the chain id as `(chain_id, 0, 0)`.
+    pub fn build_for_signing(&self) -> Vec<u8> {
+        let mut stream = RlpStream::new();
+        stream.begin_unbounded_list();
+        self.append_fields(&mut stream);
+        if self.tx_type == EvmTxType::Legacy {
+            stream.append(&self.chain_id);
+            stream.append(&0u8);
+            stream.append(&0u8);
+        }
+        stream.finalize_unbounded_list();
+
+        self.envelope(&stream.out())
+    }
+
+    /// Broadcastable transaction. `r` and `s` are big-endian scalars.
+    pub fn build_with_signature(&self, recovery_id: u8, r: &[u8], s: &[u8]) -> Vec<u8> {
+        let v = match self.tx_type {
+            EvmTxType::Legacy => self.chain_id as u128 * 2 + 35 + recovery_id as u128,
+            EvmTxType::Eip2930 | EvmTxType::Eip1559 => recovery_id as u128,
+        };
+
+        let mut stream = RlpStream::new();
+        stream.begin_unbounded_list();
+        self.append_fields(&mut stream);
+        stream.append(&v);
+        // Scalars are encoded as integers, without leading zeros
+        stream.append(&trim_leading_zeros(r));
+        stream.append(&trim_leading_zeros(s));
+        stream.finalize_unbounded_list();
+
+        self.envelope(&stream.out())
+    }
+}
+
+fn trim_leading_zeros(bytes: &[u8]) -> Vec<u8> {
+    let start = bytes.iter().position(|byte| *byte != 0).unwrap_or(bytes.len());
+    bytes[start..].to_vec()
+}
+
+/// How a contract is deployed, to predict its address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde", tag = "kind", rename_all = "snake_case")]
+pub enum EvmDeployment {
+    /// Deployment transaction sent with `nonce` by the address derived at `path`.
+    Create { path: String, nonce: u64 },
+    /// `CREATE2` from `factory`, all fields 0x-prefixed hex.
+    Create2 { factory: String, salt: String, init_code_hash: String },
+}
+
+/// Address of a contract deployed by `sender` with `nonce`: `keccak256(rlp([sender, nonce]))[12..]`.
+pub fn create_address(sender: &Address, nonce: u64) -> Address {
+    let mut stream = RlpStream::new_list(2);
+    stream.append(&sender.to_vec());
+    stream.append(&nonce);
+    keccak256(&stream.out())[12..].try_into().expect("Array conversion failed")
+}
+
+/// Address of a contract deployed by `factory` with `CREATE2`:
+/// `keccak256(0xff ++ factory ++ salt ++ keccak256(init_code))[12..]`.
+pub fn create2_address(factory: &Address, salt: &[u8; 32], init_code_hash: &[u8; 32]) -> Address {
+    let preimage = [&[0xff], factory.as_slice(), salt, init_code_hash].concat();
+    keccak256(&preimage)[12..].try_into().expect("Array conversion failed")
+}
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(crate = "near_sdk::serde")]
 pub struct PreparedEvmTransaction {
-    pub omni_evm_tx: EVMTransaction,
+    pub evm_tx: EvmTransaction,
     pub tx_hash: [u8; 32]
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+/// Contract and storage slots an EIP-2930 or EIP-1559 transaction declares it will access.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccessListItem {
+    /// 0x-prefixed hex address.
+    pub address: String,
+    /// 0x-prefixed hex 32-byte slots.
+    pub storage_keys: Vec<String>,
+}
+
+impl AccessListItem {
+    fn parse(&self, index: usize) -> Result<(Address, Vec<[u8; 32]>), EvmTxError> {
+        let storage_keys = self
+            .storage_keys
+            .iter()
+            .enumerate()
+            .map(|(i, storage_key)| parse_bytes32(&format!("access_list[{}].storage_keys[{}]", index, i), storage_key))
+            .collect::<Result<_, _>>()?;
+
+        Ok((parse_address(&format!("access_list[{}].address", index), &self.address)?, storage_keys))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct EvmTransactionRequest {
+    /// EIP-1559 unless set.
+    #[serde(default)]
+    pub tx_type: EvmTxType,
     pub nonce: u64,
-    pub to: String,
+    /// Leave empty to deploy `data` as a contract.
+    #[serde(default)]
+    pub to: Option<String>,
+    /// Quantities are decimal or 0x-prefixed hex strings of up to 256 bits.
     pub value: String,
+    /// Required by EIP-1559 transactions.
+    #[serde(default)]
     pub max_priority_fee_per_gas: String,
+    /// Required by EIP-1559 transactions.
+    #[serde(default)]
     pub max_fee_per_gas: String,
+    /// Required by legacy and EIP-2930 transactions.
+    #[serde(default)]
+    pub gas_price: Option<String>,
     pub gas_limit: String,
     pub chain_id: u64,
     pub data: Option<Vec<u8>>,
+    /// Contract call to encode as calldata, instead of passing raw `data`.
+    #[serde(default)]
+    pub call: Option<EvmCall>,
+    /// Not supported by legacy transactions.
+    #[serde(default)]
+    pub access_list: Vec<AccessListItem>,
 }
 
 #[near]
 impl Contract {
-    pub fn prepare_evm_tx(&mut self, tx_request: EvmTransactionRequest) -> PreparedEvmTransaction {
+    #[handle_result]
+    pub fn prepare_evm_tx(&mut self, tx_request: EvmTransactionRequest) -> Result<PreparedEvmTransaction, EvmTxError> {
         log!("Starting prepare_evm_tx");
 
-        let to_address = parse_eth_address(tx_request.to.trim_start_matches("0x"));
+        let to_address = tx_request.to.as_deref().map(|to| parse_address("to", to)).transpose()?;
+        let input = match (tx_request.data, &tx_request.call) {
+            (Some(_), Some(_)) => return Err(invalid("call", "can't be combined with data")),
+            (None, Some(_)) if to_address.is_none() => return Err(invalid("call", "needs a contract in to")),
+            (None, Some(call)) => call.encode()?,
+            (data, None) => data.unwrap_or_default(),
+        };
+        if to_address.is_none() && input.is_empty() {
+            return Err(EvmTxError::MissingInitCode);
+        }
+        let (gas_price, max_fee_per_gas, max_priority_fee_per_gas) = match tx_request.tx_type {
+            EvmTxType::Legacy | EvmTxType::Eip2930 => {
+                let gas_price = tx_request.gas_price.as_deref().unwrap_or_default();
+                (parse_quantity("gas_price", gas_price)?, U256::zero(), U256::zero())
+            }
+            EvmTxType::Eip1559 => (
+                U256::zero(),
+                parse_quantity("max_fee_per_gas", &tx_request.max_fee_per_gas)?,
+                parse_quantity("max_priority_fee_per_gas", &tx_request.max_priority_fee_per_gas)?,
+            ),
+        };
+
+        if tx_request.tx_type == EvmTxType::Legacy && !tx_request.access_list.is_empty() {
+            return Err(EvmTxError::AccessListNotSupported);
+        }
 
-        let omni_evm_tx = TransactionBuilder::new::<EVM>()
-            .nonce(tx_request.nonce)
-            .to(to_address)
-            .value(tx_request.value.parse::<u128>().unwrap())
-            .input(tx_request.data.unwrap_or(vec![]))
-            .max_priority_fee_per_gas(tx_request.max_priority_fee_per_gas.parse::<u128>().unwrap())
-            .max_fee_per_gas(tx_request.max_fee_per_gas.parse::<u128>().unwrap()) 
-            .gas_limit(tx_request.gas_limit.parse::<u128>().unwrap())
-            .chain_id(tx_request.chain_id)
-            .build();
+        let evm_tx = EvmTransaction {
+            tx_type: tx_request.tx_type,
+            chain_id: tx_request.chain_id,
+            nonce: tx_request.nonce,
+            to: to_address,
+            value: parse_quantity("value", &tx_request.value)?,
+            input,
+            gas_limit: parse_quantity("gas_limit", &tx_request.gas_limit)?,
+            gas_price,
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+            access_list: tx_request
+                .access_list
+                .iter()
+                .enumerate()
+                .map(|(i, item)| item.parse(i))
+                .collect::<Result<_, _>>()?,
+        };
 
-        let encoded_tx = omni_evm_tx.build_for_signing();
+        let encoded_tx = evm_tx.build_for_signing();
         let tx_hash = keccak256(&encoded_tx);
 
-        PreparedEvmTransaction {
-            omni_evm_tx,
+        Ok(PreparedEvmTransaction {
+            evm_tx,
             tx_hash: tx_hash.try_into().expect("Array conversion failed")
-        }
+        })
     }
 
+    /// Checksummed address a contract deployed as `deployment` will get.
+    #[handle_result]
+    pub fn predict_evm_contract_address(&self, deployment: EvmDeployment) -> Result<String, EvmTxError> {
+        let address = match deployment {
+            EvmDeployment::Create { path, nonce } => {
+                create_address(&evm_address_bytes(&self.derived_public_key(&path)), nonce)
+            }
+            EvmDeployment::Create2 { factory, salt, init_code_hash } => create2_address(
+                &parse_address("factory", &factory)?,
+                &parse_bytes32("salt", &salt)?,
+                &parse_bytes32("init_code_hash", &init_code_hash)?,
+            ),
+        };
+
+        Ok(to_checksum_address(&address))
+    }
+
+    /// Assemble the signed transaction once `signature` is confirmed to come from `expected_address`.
+    #[handle_result]
     pub fn finalize_evm_tx(
         &self,
-        omni_evm_tx: EVMTransaction,
-        signature: SignResult
-    ) -> String {
+        prepared_evm_transaction: PreparedEvmTransaction,
+        signature: SignResult,
+        expected_address: String,
+    ) -> Result<String, SignatureError> {
+        let PreparedEvmTransaction { evm_tx, tx_hash } = prepared_evm_transaction;
+        // EVM only accepts canonical S, with `v` matching the normalized signature
+        let signature = normalize_s(signature)?;
+        verify_evm_signature(&tx_hash, &signature, &parse_evm_address_hex(&expected_address)?)?;
+
         let mut r_bytes = hex::decode(&signature.big_r.affine_point).expect("Invalid r hex");
         r_bytes = r_bytes[1..].to_vec();
-    
+
         let s_bytes = hex::decode(&signature.s.scalar).expect("Invalid s hex");
-    
-        let v = signature.recovery_id as u64;
-    
-        let omni_signature = OmniSignature { v, r: r_bytes, s: s_bytes };
-        let tx = omni_evm_tx.build_with_signature(&omni_signature);
-    
-        format!("0x{}", hex::encode(tx))
+
+        let tx = evm_tx.build_with_signature(signature.recovery_id, &r_bytes, &s_bytes);
+
+        Ok(format!("0x{}", hex::encode(tx)))
     }
 }
 
@@ -80,23 +395,49 @@ impl Contract {
 mod tests {
     use super::*;
     use signer::{SerializableAffinePoint, SerializableScalar};
+    use test_utils::{sign_hash, sync_test_mpc_public_key};
+
+    /// Private key and address of the EIP-155 example transaction.
+    const EIP155_KEY: [u8; 32] = [0x46; 32];
+    const EIP155_ADDRESS: &str = "0x9d8A62f656a8d1615C1294fd71e9CFb3E4855A4F";
+
+    fn eip155_tx_request(tx_type: EvmTxType) -> EvmTransactionRequest {
+        EvmTransactionRequest {
+            tx_type,
+            to: Some("0x3535353535353535353535353535353535353535".to_string()),
+            value: "1000000000000000000".to_string(),
+            nonce: 9,
+            max_priority_fee_per_gas: String::new(),
+            max_fee_per_gas: String::new(),
+            gas_price: Some("20000000000".to_string()),
+            gas_limit: "21000".to_string(),
+            chain_id: 1,
+            data: None,
+            call: None,
+            access_list: vec![],
+        }
+    }
 
     #[test]
     fn test_evm_tx() {
         let tx_request = EvmTransactionRequest {
-            to: "0x4174678c78fEaFd778c1ff319D5D326701449b25".to_string(),
+            tx_type: EvmTxType::Eip1559,
+            to: Some("0x4174678c78fEaFd778c1ff319D5D326701449b25".to_string()),
             value: "1000000000000".to_string(),
             nonce: 26,
             max_priority_fee_per_gas: "25302576".to_string(),
             max_fee_per_gas: "63015311300".to_string(),
+            gas_price: None,
             gas_limit: "21000".to_string(),
             chain_id: 11155111,
-            data: None
+            data: None,
+            call: None,
+            access_list: vec![],
         };
 
         let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
 
-        let prepared_evm_transaction = contract.prepare_evm_tx(tx_request);
+        let prepared_evm_transaction = contract.prepare_evm_tx(tx_request.clone()).unwrap();
 
         assert_eq!(prepared_evm_transaction.tx_hash, [50, 172, 153, 187, 22, 209, 9, 234, 4, 113, 24, 3, 39, 17, 96, 234, 218, 104, 205, 240, 26, 39, 255, 75, 99, 21, 218, 76, 158, 98, 60, 244]);
 
@@ -110,9 +451,258 @@ mod tests {
             recovery_id: 1,
         };
 
-        let final_tx = contract.finalize_evm_tx(prepared_evm_transaction.omni_evm_tx, signature);
+        let final_tx = contract.finalize_evm_tx(
+            prepared_evm_transaction,
+            signature,
+            "0xBD369F12f46c24837aa6BB4F8aBedE5cbEE6E35a".to_string()
+        ).unwrap();
 
         assert_eq!(final_tx, "0x02f87383aa36a71a8401821630850eac0157c4825208944174678c78feafd778c1ff319d5d326701449b2585e8d4a5100080c001a00f8dcfe487cc9173251a101b4a10b74831edb4293c9338041b8f7dde538454d9a01405c2dc3048d279bed72c70aaf65fcee65b6fcc4116b0942601bbf60ed0e136".to_string());
+
+        // The same signature with S negated and the recovery id flipped
+        let high_s_signature = SignResult {
+            big_r: SerializableAffinePoint {
+                affine_point: "030F8DCFE487CC9173251A101B4A10B74831EDB4293C9338041B8F7DDE538454D9".to_string(),
+            },
+            s: SerializableScalar {
+                scalar: "EBFA3D23CFB72D864128D38F5509A02FD4536D1A6E31EFA799D0A296C165600B".to_string(),
+            },
+            recovery_id: 0,
+        };
+
+        let prepared_evm_transaction = contract.prepare_evm_tx(tx_request).unwrap();
+        let high_s_final_tx = contract.finalize_evm_tx(
+            prepared_evm_transaction,
+            high_s_signature,
+            "0xBD369F12f46c24837aa6BB4F8aBedE5cbEE6E35a".to_string()
+        ).unwrap();
+
+        assert_eq!(high_s_final_tx, final_tx);
+    }
+
+    #[test]
+    fn test_legacy_evm_tx() {
+        let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+
+        let prepared_evm_transaction = contract.prepare_evm_tx(eip155_tx_request(EvmTxType::Legacy)).unwrap();
+        assert_eq!(
+            hex::encode(prepared_evm_transaction.evm_tx.build_for_signing()),
+            "ec098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a764000080018080"
+        );
+        assert_eq!(
+            hex::encode(prepared_evm_transaction.tx_hash),
+            "daf5a779ae972f972197303d7b574746c7ef83eadac0f2791ad23db92e4c8e53"
+        );
+
+        let signature = SignResult {
+            big_r: SerializableAffinePoint {
+                affine_point: "0228EF61340BD939BC2195FE537567866003E1A15D3C71FF63E1590620AA636276".to_string(),
+            },
+            s: SerializableScalar {
+                scalar: "67CBE9D8997F761AECB703304B3800CCF555C9F3DC64214B297FB1966A3B6D83".to_string(),
+            },
+            recovery_id: 0,
+        };
+
+        // v is chain_id * 2 + 35 + recovery_id
+        let final_tx = contract.finalize_evm_tx(prepared_evm_transaction, signature, EIP155_ADDRESS.to_string()).unwrap();
+        assert_eq!(final_tx, "0xf86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83");
+
+        // Legacy and EIP-2930 transactions are priced by gas_price
+        let mut tx_request = eip155_tx_request(EvmTxType::Legacy);
+        tx_request.gas_price = None;
+        assert_eq!(
+            contract.prepare_evm_tx(tx_request).unwrap_err(),
+            EvmTxError::MissingField { field: "gas_price".to_string() }
+        );
+    }
+
+    #[test]
+    fn test_eip2930_evm_tx() {
+        let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+
+        let prepared_evm_transaction = contract.prepare_evm_tx(eip155_tx_request(EvmTxType::Eip2930)).unwrap();
+        let signing_payload = prepared_evm_transaction.evm_tx.build_for_signing();
+        assert_eq!(
+            hex::encode(&signing_payload),
+            "01eb01098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a764000080c0"
+        );
+        assert_eq!(prepared_evm_transaction.tx_hash.to_vec(), keccak256(&signing_payload));
+
+        let signature = sign_hash(&EIP155_KEY, &prepared_evm_transaction.tx_hash);
+        let recovery_id = signature.recovery_id;
+        let final_tx = contract.finalize_evm_tx(prepared_evm_transaction, signature, EIP155_ADDRESS.to_string()).unwrap();
+
+        // Typed transactions carry the bare recovery id as v
+        let final_tx = hex::decode(final_tx.trim_start_matches("0x")).unwrap();
+        assert_eq!(final_tx[0], 0x01);
+        let fields = rlp::Rlp::new(&final_tx[1..]);
+        assert_eq!(fields.item_count().unwrap(), 11);
+        assert_eq!(fields.val_at::<u8>(8).unwrap(), recovery_id);
     }
-}
 
+    #[test]
+    fn test_evm_tx_access_list() {
+        let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+        let access_list = vec![AccessListItem {
+            address: "0xde0b295669a9fd93d5f28d9ec85e40f4cb697bae".to_string(),
+            storage_keys: vec![
+                "0x0000000000000000000000000000000000000000000000000000000000000003".to_string(),
+                "0x0000000000000000000000000000000000000000000000000000000000000007".to_string(),
+            ],
+        }];
+
+        let mut tx_request = eip155_tx_request(EvmTxType::Eip2930);
+        tx_request.access_list = access_list.clone();
+        let prepared_evm_transaction = contract.prepare_evm_tx(tx_request).unwrap();
+        assert_eq!(
+            hex::encode(prepared_evm_transaction.evm_tx.build_for_signing()),
+            "01f88701098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a764000080f85bf85994de0b295669a9fd93d5f28d9ec85e40f4cb697baef842a00000000000000000000000000000000000000000000000000000000000000003a00000000000000000000000000000000000000000000000000000000000000007"
+        );
+
+        // The access list is also part of the signed transaction
+        let signature = sign_hash(&EIP155_KEY, &prepared_evm_transaction.tx_hash);
+        let final_tx = contract.finalize_evm_tx(prepared_evm_transaction, signature, EIP155_ADDRESS.to_string()).unwrap();
+        let final_tx = hex::decode(final_tx.trim_start_matches("0x")).unwrap();
+        let signed_access_list = rlp::Rlp::new(&final_tx[1..]).at(7).unwrap();
+        assert_eq!(signed_access_list.item_count().unwrap(), 1);
+        assert_eq!(signed_access_list.at(0).unwrap().at(1).unwrap().item_count().unwrap(), 2);
+
+        // Legacy transactions have no access list
+        let mut tx_request = eip155_tx_request(EvmTxType::Legacy);
+        tx_request.access_list = access_list;
+        assert_eq!(contract.prepare_evm_tx(tx_request).unwrap_err(), EvmTxError::AccessListNotSupported);
+    }
+
+    #[test]
+    fn test_evm_deployment() {
+        let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+
+        // Deployments have an empty `to` and carry the init code
+        let mut tx_request = eip155_tx_request(EvmTxType::Eip1559);
+        tx_request.to = None;
+        tx_request.max_fee_per_gas = "20000000000".to_string();
+        tx_request.max_priority_fee_per_gas = "1000000000".to_string();
+        tx_request.data = Some(vec![0x60, 0x00]);
+        let prepared_evm_transaction = contract.prepare_evm_tx(tx_request.clone()).unwrap();
+        let signing_payload = prepared_evm_transaction.evm_tx.build_for_signing();
+        let fields = rlp::Rlp::new(&signing_payload[1..]);
+        assert!(fields.at(5).unwrap().is_empty());
+        assert_eq!(fields.val_at::<Vec<u8>>(7).unwrap(), vec![0x60, 0x00]);
+
+        tx_request.data = None;
+        assert_eq!(contract.prepare_evm_tx(tx_request).unwrap_err(), EvmTxError::MissingInitCode);
+    }
+
+    #[test]
+    fn test_predict_evm_contract_address() {
+        let sender: Address = hex::decode("6ac7ea33f8831ea9dcc53393aaa88b25a785dbf0").unwrap().try_into().unwrap();
+        assert_eq!(hex::encode(create_address(&sender, 0)), "cd234a471b72ba2f1ccf0a70fcaba648a5eecd8d");
+        assert_eq!(hex::encode(create_address(&sender, 1)), "343c43a37d37dff08ae8c4a11544c718abb4fcf8");
+
+        let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+        sync_test_mpc_public_key(&mut contract);
+        let derived = parse_evm_address_hex(&contract.get_evm_address("alice.testnet".to_string()).address).unwrap();
+        assert_eq!(
+            contract.predict_evm_contract_address(EvmDeployment::Create { path: "alice.testnet".to_string(), nonce: 3 }).unwrap(),
+            to_checksum_address(&create_address(&derived, 3))
+        );
+
+        // EIP-1014 examples, with init code 0x00
+        let init_code_hash = format!("0x{}", hex::encode(keccak256(&[0x00])));
+        let create2 = |factory: &str, salt: &str| {
+            contract.predict_evm_contract_address(EvmDeployment::Create2 {
+                factory: factory.to_string(),
+                salt: salt.to_string(),
+                init_code_hash: init_code_hash.clone(),
+            })
+            .unwrap()
+        };
+        assert_eq!(
+            create2("0x0000000000000000000000000000000000000000", &format!("0x{}", "00".repeat(32))),
+            "0x4D1A2e2bB4F88F0250f26Ffff098B0b30B26BF38"
+        );
+        assert_eq!(
+            create2("0xdeadbeef00000000000000000000000000000000", &format!("0x{}feed{}", "00".repeat(12), "00".repeat(18))),
+            "0xD04116cDd17beBE565EB2422F2497E06cC1C9833"
+        );
+    }
+
+    #[test]
+    fn test_evm_tx_quantities() {
+        assert_eq!(parse_quantity("value", "0x5af3107a4000").unwrap(), U256::from(100_000_000_000_000u64));
+        assert_eq!(parse_quantity("value", "100000000000000").unwrap(), U256::from(100_000_000_000_000u64));
+        assert_eq!(parse_quantity("value", &format!("0x{}", "f".repeat(64))).unwrap(), U256::MAX);
+        assert_eq!(
+            parse_quantity("value", &format!("0x1{}", "0".repeat(64))).unwrap_err(),
+            EvmTxError::InvalidField { field: "value".to_string(), reason: "exceeds 256 bits".to_string() }
+        );
+        assert!(parse_quantity("value", "0x").is_err());
+        assert!(parse_quantity("value", "1.5").is_err());
+
+        let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+
+        // Hex and decimal requests build the same transaction
+        let mut tx_request = eip155_tx_request(EvmTxType::Legacy);
+        let decimal_tx_hash = contract.prepare_evm_tx(tx_request.clone()).unwrap().tx_hash;
+        tx_request.value = "0xde0b6b3a7640000".to_string();
+        tx_request.gas_price = Some("0x4a817c800".to_string());
+        tx_request.gas_limit = "0x5208".to_string();
+        assert_eq!(contract.prepare_evm_tx(tx_request.clone()).unwrap().tx_hash, decimal_tx_hash);
+
+        // Token-sized values don't fit in 128 bits
+        tx_request.value = "1000000000000000000000000000000000000000000".to_string();
+        let prepared_evm_transaction = contract.prepare_evm_tx(tx_request.clone()).unwrap();
+        assert!(prepared_evm_transaction.evm_tx.value > U256::from(u128::MAX));
+
+        // Errors name the offending field
+        tx_request.gas_limit = "lots".to_string();
+        let error = contract.prepare_evm_tx(tx_request.clone()).unwrap_err();
+        assert!(error.to_string().starts_with("Invalid gas_limit:"));
+
+        tx_request.gas_limit = "21000".to_string();
+        tx_request.tx_type = EvmTxType::Eip1559;
+        tx_request.access_list = vec![AccessListItem { address: "0x35".to_string(), storage_keys: vec![] }];
+        tx_request.max_fee_per_gas = "1".to_string();
+        assert_eq!(
+            contract.prepare_evm_tx(tx_request.clone()).unwrap_err(),
+            EvmTxError::MissingField { field: "max_priority_fee_per_gas".to_string() }
+        );
+        tx_request.max_priority_fee_per_gas = "1".to_string();
+        assert_eq!(
+            contract.prepare_evm_tx(tx_request).unwrap_err().to_string(),
+            "Invalid access_list[0].address: expected 20 bytes of hex"
+        );
+    }
+
+    #[test]
+    fn test_evm_tx_call() {
+        let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+        let transfer = EvmCall {
+            signature: "transfer(address,uint256)".to_string(),
+            args: vec![
+                near_sdk::serde_json::json!("0x3535353535353535353535353535353535353535"),
+                near_sdk::serde_json::json!("1000"),
+            ],
+        };
+
+        // The call is signed as the calldata it encodes to
+        let mut tx_request = eip155_tx_request(EvmTxType::Eip2930);
+        tx_request.call = Some(transfer.clone());
+        let prepared_evm_transaction = contract.prepare_evm_tx(tx_request.clone()).unwrap();
+        assert_eq!(
+            format!("0x{}", hex::encode(&prepared_evm_transaction.evm_tx.input)),
+            contract.encode_evm_call(transfer).unwrap()
+        );
+
+        tx_request.data = Some(vec![0xa9]);
+        assert_eq!(
+            contract.prepare_evm_tx(tx_request.clone()).unwrap_err().to_string(),
+            "Invalid call: can't be combined with data"
+        );
+
+        tx_request.data = None;
+        tx_request.to = None;
+        assert!(contract.prepare_evm_tx(tx_request).is_err());
+    }
+}
@@ -151,10 +151,10 @@ impl Contract {
 
         let data_digest = {
             let mut hasher = Keccak256::new();
-            hasher.update(&nonce_bytes);
+            hasher.update(nonce_bytes);
             hasher.update(&sender);
-            hasher.update(&recipient_script);
-            hasher.update(&kernel_responses_digest);
+            hasher.update(recipient_script);
+            hasher.update(kernel_responses_digest);
             let hash = hasher.finalize();
             let mut out = [0u8; 32];
             out.copy_from_slice(&hash);
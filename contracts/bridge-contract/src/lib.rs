@@ -1,19 +1,145 @@
-use near_sdk::{near, AccountId, PanicOnDefault};
+use near_sdk::{
+    borsh::{BorshDeserialize, BorshSerialize},
+    near,
+    serde::{Deserialize, Serialize},
+    store::{IterableMap, IterableSet, LookupMap, LookupSet, Vector},
+    env, AccountId, BorshStorageKey, NearToken, PanicOnDefault, PublicKey,
+};
+use approval::{Approval, ApprovalPolicy};
+use limits::{OutflowCap, OutflowWindow};
+use lp::LiquidityProvider;
+use pool::PoolDebit;
+use registry::{RequestId, SignatureRequest};
+use roles::{Role, RolePermissions};
+use signer::SignerVersion;
+use storage::StorageAccount;
+use treasury::FeeSchedule;
+use schemars::JsonSchema;
 
+pub mod abi;
+pub mod admin;
+pub mod approval;
 pub mod btc;
+pub mod derivation;
+pub mod ed25519;
+pub mod events;
 pub mod evm;
 pub mod krnl;
+pub mod light_client;
+pub mod limits;
+pub mod lp;
+pub mod migration;
+pub mod pool;
+pub mod registry;
+pub mod roles;
 pub mod swap_krnl;
 pub mod signer;
 pub mod sign;
+pub mod storage;
+pub mod treasury;
+pub mod upgrade;
+pub mod verify;
+
+#[cfg(test)]
+mod test_utils;
+
+/// Chains the bridge can sign transactions for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Chain {
+    Bitcoin,
+    Evm,
+    Solana,
+    Near,
+}
+
+/// Prefixes of the stored collections, numbered by their position: new keys are appended.
+#[derive(BorshStorageKey, BorshSerialize)]
+pub enum StorageKey {
+    Requests,
+    AccountRequests,
+    AccountRequestsInner { account_id: AccountId },
+    /// Wasm of the staged upgrade, kept out of the contract state so it isn't loaded on every call.
+    StagedCode,
+    RoleMembers,
+    RoleMembersInner { role: Role },
+    RolePermissions,
+    Lps,
+    LpByBtcPublicKey,
+    PoolBalances,
+    ProvenDeposits,
+    PoolDebits,
+    StorageAccounts,
+    FeeSchedules,
+    TreasuryBalances,
+    PendingFees,
+    OutflowCaps,
+    Outflows,
+    ApprovalThresholds,
+    Approvals,
+    ApprovalQueue,
+}
 
 #[derive(Debug, PanicOnDefault)]
 #[near(contract_state)]
 pub struct Contract {
+    /// Account allowed to rotate the signer, change settings and pause entry points.
+    pub owner_id: AccountId,
     pub signer_account: AccountId,
-    // transaction_list: String, // Type is wrong, should be a list of transaction and corresponding status (pending, signed...)
-    // balance: String, // Type is wrong, should be the balance of each pool
-    // lp_list: String, // Type is wrong, should be a list of lp and corresponding status (pending, signed...) {risk, addresses, ...}
+    /// Protocol spoken by `signer_account`.
+    pub signer_version: SignerVersion,
+    /// Root MPC public key, fetched from the signer with `sync_mpc_public_key`.
+    pub mpc_public_key: Option<PublicKey>,
+    /// Deposit attached to each signature request sent to the signer.
+    pub signature_deposit: NearToken,
+    /// Signature requests and their status, keyed by their deterministic id.
+    pub requests: LookupMap<RequestId, SignatureRequest>,
+    /// Ids of the requests created by each account, oldest first.
+    pub account_requests: LookupMap<AccountId, Vector<RequestId>>,
+    /// Entry points currently rejecting calls.
+    pub paused_entry_points: Vec<admin::EntryPoint>,
+    /// Minimum time between staging an upgrade and deploying it.
+    pub upgrade_delay_ns: u64,
+    /// Upgrade waiting for its delay, its wasm is stored under `StorageKey::StagedCode`.
+    pub staged_upgrade: Option<upgrade::StagedUpgrade>,
+    /// Accounts holding each role.
+    pub role_members: LookupMap<Role, IterableSet<AccountId>>,
+    pub role_permissions: LookupMap<Role, RolePermissions>,
+    /// Registered liquidity providers.
+    pub lps: IterableMap<AccountId, LiquidityProvider>,
+    /// Owner of each LP's BTC public key, as KRNL identifies LPs by it.
+    pub lp_by_btc_public_key: LookupMap<String, AccountId>,
+    /// Liquidity recorded in each LP's pool on each chain, in the chain's smallest unit.
+    pub pool_balances: LookupMap<(AccountId, Chain), u128>,
+    /// Deposits already credited or being proven, by chain, transaction id and output index.
+    pub proven_deposits: LookupSet<(Chain, String, u32)>,
+    /// Liquidity taken from a pool by each swap payout.
+    pub pool_debits: LookupMap<RequestId, PoolDebit>,
+    /// NEP-145 storage paid by each account for the records it created.
+    pub storage_accounts: LookupMap<AccountId, StorageAccount>,
+    /// Fee taken on the swap payouts of each chain.
+    pub fee_schedules: LookupMap<Chain, FeeSchedule>,
+    /// Fees collected on each chain and not withdrawn yet.
+    pub treasury_balances: LookupMap<Chain, u128>,
+    /// Fees paid by swap payouts, credited to the treasury once the payout is finalized.
+    pub pending_fees: LookupMap<RequestId, (Chain, u128)>,
+    /// Caps on the value signed out of each chain, bridge-wide under `None` or per LP.
+    pub outflow_caps: LookupMap<(Option<AccountId>, Chain), OutflowCap>,
+    /// Value recently signed out under each cap.
+    pub outflows: LookupMap<(Option<AccountId>, Chain), OutflowWindow>,
+    /// Guardians needed to approve transfers above the threshold of their chain.
+    pub approval_policy: ApprovalPolicy,
+    pub approval_thresholds: LookupMap<Chain, u128>,
+    /// Guardian decisions on transfers above the threshold, kept after they are resolved.
+    pub approvals: LookupMap<RequestId, Approval>,
+    /// Transfers still waiting for the guardians.
+    pub approval_queue: IterableSet<RequestId>,
+    /// Shorter upgrade delay waiting for the current delay to pass.
+    pub pending_upgrade_delay: Option<upgrade::PendingUpgradeDelay>,
+    /// Light client proving BTC deposits, which can't be recorded until it is set.
+    pub btc_light_client: Option<AccountId>,
+    /// Blocks a BTC deposit needs on top of it before it is credited.
+    pub btc_deposit_confirmations: u64,
 }
 
 #[near]
@@ -21,8 +147,39 @@ impl Contract {
     #[private]
     #[init]
     pub fn new(signer_account: AccountId) -> Self {
+        migration::write_state_version();
+
         Self {
+            owner_id: env::current_account_id(),
             signer_account,
+            signer_version: SignerVersion::V1,
+            mpc_public_key: None,
+            signature_deposit: sign::DEFAULT_SIGNATURE_DEPOSIT,
+            requests: LookupMap::new(StorageKey::Requests),
+            account_requests: LookupMap::new(StorageKey::AccountRequests),
+            paused_entry_points: Vec::new(),
+            upgrade_delay_ns: upgrade::DEFAULT_UPGRADE_DELAY_NS,
+            staged_upgrade: None,
+            role_members: LookupMap::new(StorageKey::RoleMembers),
+            role_permissions: roles::default_role_permissions(),
+            lps: IterableMap::new(StorageKey::Lps),
+            lp_by_btc_public_key: LookupMap::new(StorageKey::LpByBtcPublicKey),
+            pool_balances: LookupMap::new(StorageKey::PoolBalances),
+            proven_deposits: LookupSet::new(StorageKey::ProvenDeposits),
+            pool_debits: LookupMap::new(StorageKey::PoolDebits),
+            storage_accounts: LookupMap::new(StorageKey::StorageAccounts),
+            fee_schedules: LookupMap::new(StorageKey::FeeSchedules),
+            treasury_balances: LookupMap::new(StorageKey::TreasuryBalances),
+            pending_fees: LookupMap::new(StorageKey::PendingFees),
+            outflow_caps: LookupMap::new(StorageKey::OutflowCaps),
+            outflows: LookupMap::new(StorageKey::Outflows),
+            approval_policy: ApprovalPolicy::default(),
+            approval_thresholds: LookupMap::new(StorageKey::ApprovalThresholds),
+            approvals: LookupMap::new(StorageKey::Approvals),
+            approval_queue: IterableSet::new(StorageKey::ApprovalQueue),
+            pending_upgrade_delay: None,
+            btc_light_client: None,
+            btc_deposit_confirmations: pool::DEFAULT_BTC_DEPOSIT_CONFIRMATIONS,
         }
     }
 
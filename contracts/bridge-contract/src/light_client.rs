@@ -0,0 +1,26 @@
+use near_sdk::{
+    borsh::{BorshDeserialize, BorshSerialize},
+    ext_contract,
+};
+
+/// Arguments of `verify_transaction_inclusion`. Hashes are in the byte order they are hashed in,
+/// the reverse of how explorers display them.
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct ProofArgs {
+    pub tx_id: [u8; 32],
+    pub tx_block_blockhash: [u8; 32],
+    /// Position of the transaction in its block.
+    pub tx_index: u64,
+    /// Sibling hashes from the transaction up to the block's merkle root.
+    pub merkle_proof: Vec<[u8; 32]>,
+    pub confirmations: u64,
+}
+
+/// Contract tracking the BTC header chain.
+#[ext_contract(ext_btc_light_client)]
+pub trait BtcLightClient {
+    /// Whether the transaction is in the given block of the main chain, with at least
+    /// `confirmations` blocks on top of it.
+    fn verify_transaction_inclusion(&self, #[serializer(borsh)] args: ProofArgs) -> bool;
+}
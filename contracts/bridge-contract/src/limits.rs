@@ -0,0 +1,246 @@
+use crate::*;
+
+use btc::BitcoinTransactionRequest;
+use events::BridgeEvent;
+use near_sdk::{env, json_types::U128, near};
+
+/// Each window is tracked in this many buckets, bounding the storage of busy windows.
+const OUTFLOW_BUCKETS: u64 = 24;
+
+/// Most value that can be signed out within `window_ns`, in the chain's smallest unit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OutflowCap {
+    #[schemars(with = "String")]
+    pub amount: U128,
+    pub window_ns: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Outflow {
+    pub cap: OutflowCap,
+    /// Value signed within the current window.
+    #[schemars(with = "String")]
+    pub used: U128,
+}
+
+/// Value signed recently, as `(bucket start, amount)` pairs, oldest first.
+///
+/// A bucket counts until its start leaves the window, so the window can hold up to one
+/// bucket of value for slightly longer than `window_ns`, never shorter.
+#[derive(Debug, Clone, Default, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct OutflowWindow {
+    buckets: Vec<(u64, u128)>,
+}
+
+impl OutflowWindow {
+    fn total(&self, cap: &OutflowCap, now: u64) -> u128 {
+        self.buckets
+            .iter()
+            .filter(|(start, _)| start.saturating_add(cap.window_ns) > now)
+            .map(|(_, amount)| amount)
+            .sum()
+    }
+
+    fn record(&mut self, cap: &OutflowCap, now: u64, amount: u128) {
+        self.buckets.retain(|(start, _)| start.saturating_add(cap.window_ns) > now);
+
+        let bucket_ns = (cap.window_ns / OUTFLOW_BUCKETS).max(1);
+        match self.buckets.last_mut() {
+            Some((start, total)) if now < start.saturating_add(bucket_ns) => *total += amount,
+            _ => self.buckets.push((now, amount)),
+        }
+    }
+}
+
+/// Satoshis a transaction takes from the address at `own_script_pubkey`: every input is spent
+/// and only the change comes back.
+pub fn btc_spent(tx_request: &BitcoinTransactionRequest, own_script_pubkey: &str) -> u128 {
+    let total_input: u128 = tx_request.inputs.iter().map(|input| input.value as u128).sum();
+    let change: u128 = tx_request
+        .outputs
+        .iter()
+        .filter(|output| output.script_pubkey.eq_ignore_ascii_case(own_script_pubkey))
+        .map(|output| output.value as u128)
+        .sum();
+
+    total_input.saturating_sub(change)
+}
+
+impl Contract {
+    /// Caps a request on `chain` counts against: the bridge's, and the LP's own for swaps paid
+    /// from its pool.
+    fn outflow_scopes(chain: Chain, lp_account_id: Option<&AccountId>) -> Vec<(Option<AccountId>, Chain)> {
+        let mut scopes = vec![(None, chain)];
+        if let Some(account_id) = lp_account_id {
+            scopes.push((Some(account_id.clone()), chain));
+        }
+        scopes
+    }
+
+    pub(crate) fn is_outflow_capped(&self, chain: Chain, lp_account_id: Option<&AccountId>) -> bool {
+        Self::outflow_scopes(chain, lp_account_id)
+            .iter()
+            .any(|scope| self.outflow_caps.contains_key(scope))
+    }
+
+    fn outflow(&self, scope: &(Option<AccountId>, Chain)) -> Option<Outflow> {
+        let cap = self.outflow_caps.get(scope)?.clone();
+        let used = self
+            .outflows
+            .get(scope)
+            .map_or(0, |window| window.total(&cap, env::block_timestamp()));
+        Some(Outflow { cap, used: U128(used) })
+    }
+
+    /// Check that signing `amount` for `request_id` stays within the caps. Resubmitting a
+    /// failed or expired request signs the same value again, so it isn't counted twice.
+    pub(crate) fn check_outflow(
+        &self,
+        request_id: &RequestId,
+        chain: Chain,
+        lp_account_id: Option<&AccountId>,
+        amount: u128,
+    ) -> Result<(), String> {
+        if self.requests.contains_key(request_id) {
+            return Ok(());
+        }
+
+        for scope in Self::outflow_scopes(chain, lp_account_id) {
+            let Some(Outflow { cap, used }) = self.outflow(&scope) else {
+                continue;
+            };
+            if used.0.saturating_add(amount) > cap.amount.0 {
+                let owner = scope.0.map_or_else(|| "the bridge".to_string(), |account_id| account_id.to_string());
+                return Err(format!(
+                    "Signing {} exceeds the {:?} outflow cap of {}: {} of {} used in the last {}ns",
+                    amount, chain, owner, used.0, cap.amount.0, cap.window_ns
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Count `amount` signed by the new request `request_id` against the caps, which it must not exceed.
+    pub(crate) fn record_outflow(
+        &mut self,
+        request_id: &RequestId,
+        chain: Chain,
+        lp_account_id: Option<&AccountId>,
+        amount: u128,
+    ) {
+        self.check_outflow(request_id, chain, lp_account_id, amount)
+            .unwrap_or_else(|e| env::panic_str(&e));
+        if self.requests.contains_key(request_id) {
+            return;
+        }
+
+        let now = env::block_timestamp();
+        for scope in Self::outflow_scopes(chain, lp_account_id) {
+            let Some(cap) = self.outflow_caps.get(&scope).cloned() else {
+                continue;
+            };
+            self.outflows.entry(scope).or_default().record(&cap, now, amount);
+        }
+    }
+}
+
+#[near]
+impl Contract {
+    /// Cap the value signed out on `chain` within a rolling window, for the whole bridge or, with
+    /// `lp_account_id`, for swaps paid from that LP's pool. A `None` cap removes it.
+    pub fn set_outflow_cap(&mut self, chain: Chain, lp_account_id: Option<AccountId>, cap: Option<OutflowCap>) {
+        self.assert_owner();
+
+        let scope = (lp_account_id.clone(), chain);
+        match cap.clone() {
+            Some(cap) => {
+                if cap.window_ns == 0 {
+                    env::panic_str("Outflow window can't be empty");
+                }
+                self.outflow_caps.insert(scope, cap);
+            }
+            None => {
+                self.outflow_caps.remove(&scope);
+                self.outflows.remove(&scope);
+            }
+        }
+        BridgeEvent::OutflowCapChanged { chain, lp_account_id, cap }.emit();
+    }
+
+    /// Cap and current usage of the bridge, or of an LP with `lp_account_id`, on `chain`.
+    pub fn get_outflow(&self, chain: Chain, lp_account_id: Option<AccountId>) -> Option<Outflow> {
+        self.outflow(&(lp_account_id, chain))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::{test_utils::VMContextBuilder, testing_env};
+    use sign::DEFAULT_SIGNATURE_DEPOSIT;
+    use test_utils::{btc_tx_request, contract_with_relayer, set_context};
+
+    const HOUR_NS: u64 = 60 * 60 * 1_000_000_000;
+
+    #[test]
+    fn test_outflow_window() {
+        let cap = OutflowCap { amount: U128(100), window_ns: 24 * HOUR_NS };
+        let mut window = OutflowWindow::default();
+
+        window.record(&cap, 0, 40);
+        window.record(&cap, HOUR_NS / 2, 20);
+        window.record(&cap, 12 * HOUR_NS, 30);
+        assert_eq!(window.buckets.len(), 2);
+        assert_eq!(window.total(&cap, 12 * HOUR_NS), 90);
+
+        // The first bucket leaves the window after a day
+        assert_eq!(window.total(&cap, 24 * HOUR_NS), 30);
+        window.record(&cap, 24 * HOUR_NS, 10);
+        assert_eq!(window.buckets, vec![(12 * HOUR_NS, 30), (24 * HOUR_NS, 10)]);
+    }
+
+    #[test]
+    fn test_btc_spent() {
+        let tx_request = btc_tx_request(&"alice.near".parse().unwrap());
+        assert_eq!(btc_spent(&tx_request, &tx_request.inputs[0].script_pubkey), 430506 - 428854);
+        assert_eq!(btc_spent(&tx_request, "0014d3ae5a5de66aa44e7d5723b74e590340b3212f46"), 430506 - 1200);
+    }
+
+    #[test]
+    fn test_sign_btc_within_cap() {
+        let alice: AccountId = "alice.near".parse().unwrap();
+        set_context(&alice, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+
+        let mut contract = contract_with_relayer(&alice);
+        // The inputs don't belong to the caller's derived address, so all of them are spent
+        let cap = OutflowCap { amount: U128(500_000), window_ns: HOUR_NS };
+        contract.set_outflow_cap(Chain::Bitcoin, None, Some(cap.clone()));
+        assert_eq!(contract.get_outflow(Chain::Evm, None), None);
+
+        let tx_request = btc_tx_request(&alice);
+        contract.sign_btc(tx_request.clone(), None, None);
+        assert_eq!(contract.get_outflow(Chain::Bitcoin, None), Some(Outflow { cap, used: U128(430506) }));
+
+        // Another transaction doesn't fit in the window, resubmitting the same one isn't counted
+        let mut other_tx_request = tx_request.clone();
+        other_tx_request.outputs[0].value += 1;
+        set_context(&alice, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.sign_btc(other_tx_request.clone(), None, None)
+        }));
+        assert!(result.is_err());
+        let request_id = contract.get_requests_for_account(alice.clone(), None, None)[0].id.clone();
+        assert!(contract.check_outflow(&request_id, Chain::Bitcoin, None, 430506).is_ok());
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(alice.clone())
+            .attached_deposit(DEFAULT_SIGNATURE_DEPOSIT)
+            .block_timestamp(HOUR_NS)
+            .build());
+        assert_eq!(contract.get_outflow(Chain::Bitcoin, None).unwrap().used, U128(0));
+        contract.sign_btc(other_tx_request, None, None);
+    }
+}
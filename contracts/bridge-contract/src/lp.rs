@@ -0,0 +1,329 @@
+use crate::*;
+
+use events::BridgeEvent;
+use near_sdk::{env, near};
+use sign::KeyDerivation;
+use verify::{parse_evm_address_hex, parse_public_key_hex};
+
+const DEFAULT_PAGE_LIMIT: u64 = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum LpStatus {
+    Active,
+    /// Kept in the registry, but its liquidity can't be used for swaps.
+    Suspended,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum RiskTier {
+    Low,
+    Medium,
+    High,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LiquidityProvider {
+    pub account_id: AccountId,
+    /// Hex-encoded compressed key controlling the LP's BTC liquidity, as sent by KRNL as `lp_pubkey`.
+    pub btc_public_key: String,
+    /// P2WPKH script of `btc_public_key`.
+    pub btc_script_pubkey: String,
+    /// Checksummed EVM addresses receiving the LP's deposits.
+    pub evm_addresses: Vec<String>,
+    pub status: LpStatus,
+    pub risk_tier: RiskTier,
+    pub registered_at: u64,
+    pub updated_at: u64,
+}
+
+/// Lowercase hex of a valid compressed secp256k1 key, so lookups don't depend on the caller's casing.
+fn normalize_btc_public_key(btc_public_key: &str) -> String {
+    let point = parse_public_key_hex(btc_public_key).unwrap_or_else(|e| env::panic_str(&e.to_string()));
+    if btc_public_key.len() != 66 {
+        env::panic_str("BTC public key must be compressed");
+    }
+
+    hex::encode(derivation::compressed_public_key(&point))
+}
+
+fn checksum_evm_addresses(evm_addresses: Vec<String>) -> Vec<String> {
+    evm_addresses
+        .iter()
+        .map(|address| {
+            let bytes = parse_evm_address_hex(address).unwrap_or_else(|e| env::panic_str(&e.to_string()));
+            derivation::to_checksum_address(&bytes)
+        })
+        .collect()
+}
+
+fn btc_script_pubkey(btc_public_key: &str) -> String {
+    let point = parse_public_key_hex(btc_public_key).unwrap_or_else(|e| env::panic_str(&e.to_string()));
+    hex::encode(derivation::p2wpkh_script_pubkey(&point))
+}
+
+impl Contract {
+    pub(crate) fn expect_lp(&self, account_id: &AccountId) -> &LiquidityProvider {
+        self.lps
+            .get(account_id)
+            .unwrap_or_else(|| env::panic_str("Unknown liquidity provider"))
+    }
+
+    /// The registered LP owning `btc_public_key`, as long as it is active.
+    pub(crate) fn active_lp_by_btc_public_key(&self, btc_public_key: &str) -> Result<&LiquidityProvider, String> {
+        let lp = self
+            .lp_by_btc_public_key
+            .get(&btc_public_key.to_lowercase())
+            .map(|account_id| self.expect_lp(account_id))
+            .ok_or_else(|| "BTC public key does not belong to a registered LP".to_string())?;
+
+        if lp.status != LpStatus::Active {
+            return Err(format!("Liquidity provider {} is not active", lp.account_id));
+        }
+        Ok(lp)
+    }
+
+    fn assert_lp_or_admin(&self, account_id: &AccountId) {
+        if env::predecessor_account_id() != *account_id {
+            self.assert_owner_or_admin();
+        }
+    }
+
+    /// Payouts are signed with the key derived for the LP's account, so that is the only key it can register.
+    fn assert_derived_btc_public_key(&self, account_id: &AccountId, btc_public_key: &str) {
+        let path = KeyDerivation::for_owner(account_id, None, None).path;
+        let derived = hex::encode(derivation::compressed_public_key(&self.derived_public_key(&path)));
+        if btc_public_key != derived {
+            env::panic_str(&format!("BTC public key must be {}, the key derived for {}", derived, account_id));
+        }
+    }
+
+    fn index_btc_public_key(&mut self, btc_public_key: &str, account_id: &AccountId) {
+        if let Some(owner) = self.lp_by_btc_public_key.get(btc_public_key) {
+            if owner != account_id {
+                env::panic_str(&format!("BTC public key is already registered by {}", owner));
+            }
+        }
+        self.lp_by_btc_public_key.insert(btc_public_key.to_string(), account_id.clone());
+    }
+}
+
+#[near]
+impl Contract {
+    /// Register `account_id` as an active LP. Only the owner and admins can onboard LPs, and
+    /// the LP must have registered storage for its record. `btc_public_key` must be the key
+    /// derived for the LP, the one `get_btc_address` returns for `account_id` as path.
+    pub fn register_lp(
+        &mut self,
+        account_id: AccountId,
+        btc_public_key: String,
+        evm_addresses: Vec<String>,
+        risk_tier: RiskTier,
+    ) -> LiquidityProvider {
+        self.assert_owner_or_admin();
+        if self.lps.contains_key(&account_id) {
+            env::panic_str("Liquidity provider is already registered");
+        }
+
+        let initial_storage_usage = env::storage_usage();
+        let btc_public_key = normalize_btc_public_key(&btc_public_key);
+        self.assert_derived_btc_public_key(&account_id, &btc_public_key);
+        self.index_btc_public_key(&btc_public_key, &account_id);
+
+        let now = env::block_timestamp();
+        let lp = LiquidityProvider {
+            account_id: account_id.clone(),
+            btc_script_pubkey: btc_script_pubkey(&btc_public_key),
+            btc_public_key,
+            evm_addresses: checksum_evm_addresses(evm_addresses),
+            status: LpStatus::Active,
+            risk_tier,
+            registered_at: now,
+            updated_at: now,
+        };
+        self.lps.insert(account_id.clone(), lp.clone());
+        self.charge_storage(&account_id, initial_storage_usage);
+
+        BridgeEvent::LpRegistered { account_id, risk_tier }.emit();
+        lp
+    }
+
+    /// Replace the LP's keys and addresses. Callable by the LP itself, the owner and admins.
+    pub fn update_lp(
+        &mut self,
+        account_id: AccountId,
+        btc_public_key: Option<String>,
+        evm_addresses: Option<Vec<String>>,
+    ) -> LiquidityProvider {
+        self.assert_lp_or_admin(&account_id);
+        let mut lp = self.expect_lp(&account_id).clone();
+        let initial_storage_usage = env::storage_usage();
+
+        if let Some(btc_public_key) = btc_public_key {
+            let btc_public_key = normalize_btc_public_key(&btc_public_key);
+            self.assert_derived_btc_public_key(&account_id, &btc_public_key);
+            self.index_btc_public_key(&btc_public_key, &account_id);
+            if lp.btc_public_key != btc_public_key {
+                self.lp_by_btc_public_key.remove(&lp.btc_public_key);
+            }
+
+            lp.btc_script_pubkey = btc_script_pubkey(&btc_public_key);
+            lp.btc_public_key = btc_public_key;
+        }
+        if let Some(evm_addresses) = evm_addresses {
+            lp.evm_addresses = checksum_evm_addresses(evm_addresses);
+        }
+
+        lp.updated_at = env::block_timestamp();
+        self.lps.insert(account_id.clone(), lp.clone());
+        self.charge_storage(&account_id, initial_storage_usage);
+
+        BridgeEvent::LpUpdated { account_id }.emit();
+        lp
+    }
+
+    pub fn set_lp_status(&mut self, account_id: AccountId, status: LpStatus) {
+        self.assert_owner_or_admin();
+
+        let lp = self
+            .lps
+            .get_mut(&account_id)
+            .unwrap_or_else(|| env::panic_str("Unknown liquidity provider"));
+        lp.status = status;
+        lp.updated_at = env::block_timestamp();
+
+        BridgeEvent::LpStatusChanged { account_id, status }.emit();
+    }
+
+    pub fn set_lp_risk_tier(&mut self, account_id: AccountId, risk_tier: RiskTier) {
+        self.assert_owner_or_admin();
+
+        let lp = self
+            .lps
+            .get_mut(&account_id)
+            .unwrap_or_else(|| env::panic_str("Unknown liquidity provider"));
+        lp.risk_tier = risk_tier;
+        lp.updated_at = env::block_timestamp();
+
+        BridgeEvent::LpRiskTierChanged { account_id, risk_tier }.emit();
+    }
+
+    /// Remove the LP from the registry. Callable by the LP itself, the owner and admins.
+    pub fn deregister_lp(&mut self, account_id: AccountId) {
+        self.assert_lp_or_admin(&account_id);
+        if self.has_pool_balance(&account_id) {
+            env::panic_str("Liquidity provider still has pool balances");
+        }
+
+        let initial_storage_usage = env::storage_usage();
+        let lp = self
+            .lps
+            .remove(&account_id)
+            .unwrap_or_else(|| env::panic_str("Unknown liquidity provider"));
+        self.lp_by_btc_public_key.remove(&lp.btc_public_key);
+        self.record_storage_usage(&account_id, initial_storage_usage);
+
+        BridgeEvent::LpDeregistered { account_id }.emit();
+    }
+
+    pub fn get_lp(&self, account_id: AccountId) -> Option<LiquidityProvider> {
+        self.lps.get(&account_id).cloned()
+    }
+
+    pub fn get_lp_by_btc_public_key(&self, btc_public_key: String) -> Option<LiquidityProvider> {
+        self.lp_by_btc_public_key
+            .get(&btc_public_key.to_lowercase())
+            .and_then(|account_id| self.lps.get(account_id))
+            .cloned()
+    }
+
+    pub fn get_lps(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<LiquidityProvider> {
+        self.lps
+            .values()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(DEFAULT_PAGE_LIMIT) as usize)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::NearToken;
+    use test_utils::{register_storage, set_context, sync_mpc_public_key_deriving};
+
+    const LP_PUBLIC_KEY: &str = "02B12224ECEC8184DBFF10316A889EBEE9F7871BD6DE358C5323FBECCE9D84FD24";
+    const OTHER_PUBLIC_KEY: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+    #[test]
+    fn test_lp_lifecycle() {
+        let owner: AccountId = "alice.near".parse().unwrap();
+        let lp: AccountId = "lp.testnet".parse().unwrap();
+        set_context(&owner, NearToken::from_yoctonear(0), vec![]);
+
+        let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+        register_storage(&mut contract, &lp);
+        sync_mpc_public_key_deriving(&mut contract, &lp, LP_PUBLIC_KEY);
+
+        // Only the key derived for the LP's account can be registered
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.register_lp(lp.clone(), OTHER_PUBLIC_KEY.to_string(), vec![], RiskTier::Low)
+        }));
+        assert!(result.is_err());
+
+        let registered = contract.register_lp(
+            lp.clone(),
+            LP_PUBLIC_KEY.to_string(),
+            vec!["0x7e5f4552091a69125d5dfcb7b8c2659029395bdf".to_string()],
+            RiskTier::Low,
+        );
+        assert_eq!(registered.btc_public_key, LP_PUBLIC_KEY.to_lowercase());
+        assert_eq!(registered.btc_script_pubkey, "00140d7d0223d302b4e8ef37050b5200b1c3306ae7ab");
+        assert_eq!(registered.evm_addresses, vec!["0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf"]);
+        assert_eq!(contract.get_lp_by_btc_public_key(LP_PUBLIC_KEY.to_string()), Some(registered));
+        assert_eq!(contract.active_lp_by_btc_public_key(LP_PUBLIC_KEY).unwrap().account_id, lp);
+
+        contract.set_lp_status(lp.clone(), LpStatus::Suspended);
+        assert!(contract.active_lp_by_btc_public_key(LP_PUBLIC_KEY).is_err());
+
+        // LPs manage their own addresses, but not their status
+        set_context(&lp, NearToken::from_yoctonear(0), vec![]);
+        let updated = contract.update_lp(lp.clone(), None, Some(vec![]));
+        assert!(updated.evm_addresses.is_empty());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.update_lp(lp.clone(), Some(OTHER_PUBLIC_KEY.to_string()), None)
+        }));
+        assert!(result.is_err());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.set_lp_status(lp.clone(), LpStatus::Active)
+        }));
+        assert!(result.is_err());
+
+        contract.deregister_lp(lp.clone());
+        assert_eq!(contract.storage_accounts[&lp].used_bytes, 0);
+        assert_eq!(contract.get_lp(lp), None);
+        assert_eq!(contract.get_lp_by_btc_public_key(LP_PUBLIC_KEY.to_string()), None);
+        assert!(contract.get_lps(None, None).is_empty());
+    }
+
+    #[test]
+    fn test_btc_public_key_is_unique() {
+        let owner: AccountId = "alice.near".parse().unwrap();
+        set_context(&owner, NearToken::from_yoctonear(0), vec![]);
+
+        let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+        for account_id in ["lp.testnet", "other-lp.testnet"] {
+            register_storage(&mut contract, &account_id.parse().unwrap());
+        }
+        sync_mpc_public_key_deriving(&mut contract, &"lp.testnet".parse().unwrap(), LP_PUBLIC_KEY);
+        contract.register_lp("lp.testnet".parse().unwrap(), LP_PUBLIC_KEY.to_string(), vec![], RiskTier::Low);
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.register_lp("other-lp.testnet".parse().unwrap(), LP_PUBLIC_KEY.to_lowercase(), vec![], RiskTier::High)
+        }));
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,140 @@
+use crate::*;
+
+use near_sdk::{env, log, near};
+
+/// Storage key holding the layout version of `STATE`, written whenever the state is initialized
+/// or migrated.
+const STATE_VERSION_KEY: &[u8] = b"STATE_VERSION";
+
+/// Layout version of `Contract`. Bump it whenever the current layout is frozen as a new `ContractVn`.
+pub const CURRENT_STATE_VERSION: u8 = 2;
+
+pub(crate) fn write_state_version() {
+    env::storage_write(STATE_VERSION_KEY, &[CURRENT_STATE_VERSION]);
+}
+
+/// Layout deployed before signature requests were tracked: only the signer account.
+#[derive(BorshSerialize, BorshDeserialize)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct ContractV1 {
+    pub signer_account: AccountId,
+}
+
+/// Every layout the contract state has been stored with, oldest first, numbered by the version
+/// stored under `STATE_VERSION_KEY`. The latest is `Contract` itself; when a field is added to a
+/// deployed layout, that layout is frozen here as a new `ContractVn`.
+pub enum VersionedContractState {
+    V1(ContractV1),
+    Current(Box<Contract>),
+}
+
+fn decode_state<T: BorshDeserialize>(state: &[u8], version: u8) -> T {
+    borsh::from_slice(state)
+        .unwrap_or_else(|_| env::panic_str(&format!("Contract state doesn't match layout version {}", version)))
+}
+
+impl VersionedContractState {
+    /// Decode the stored state with the layout of its stored version. Only the state deployed
+    /// before versioning has no version, and it has the `ContractV1` layout.
+    pub fn read() -> Self {
+        let state = env::storage_read(b"STATE").unwrap_or_else(|| env::panic_str("Contract is not initialized"));
+        let version = match env::storage_read(STATE_VERSION_KEY) {
+            Some(version) => *version.first().unwrap_or_else(|| env::panic_str("Empty contract state version")),
+            None => 1,
+        };
+
+        match version {
+            1 => VersionedContractState::V1(decode_state(&state, version)),
+            CURRENT_STATE_VERSION => VersionedContractState::Current(Box::new(decode_state(&state, version))),
+            _ => env::panic_str(&format!("Unknown contract state version {}", version)),
+        }
+    }
+
+    pub fn into_current(self) -> Contract {
+        match self {
+            VersionedContractState::V1(ContractV1 { signer_account }) => Contract::new(signer_account),
+            VersionedContractState::Current(contract) => *contract,
+        }
+    }
+}
+
+#[near]
+impl Contract {
+    /// Upgrade the stored state to the current layout, to be called right after deploying new code.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let state = VersionedContractState::read();
+        if matches!(state, VersionedContractState::Current(_)) {
+            log!("Contract state is already up to date");
+        }
+
+        let contract = state.into_current();
+        write_state_version();
+        contract
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::NearToken;
+    use sign::DEFAULT_SIGNATURE_DEPOSIT;
+    use signer::SignerVersion;
+    use test_utils::{btc_tx_request, contract_with_relayer, set_context};
+
+    #[test]
+    fn test_migrate_from_v1() {
+        let alice: AccountId = "alice.testnet".parse().unwrap();
+        set_context(&alice, NearToken::from_yoctonear(0), vec![]);
+
+        env::state_write(&ContractV1 { signer_account: "v1.signer-prod.testnet".parse().unwrap() });
+        let contract = Contract::migrate();
+
+        assert_eq!(contract.get_signer_account(), "v1.signer-prod.testnet".parse::<AccountId>().unwrap());
+        assert_eq!(contract.get_signer_version(), SignerVersion::V1);
+        assert_eq!(contract.get_owner_id(), env::current_account_id());
+        assert_eq!(contract.get_signature_deposit(), DEFAULT_SIGNATURE_DEPOSIT);
+        assert_eq!(contract.get_mpc_public_key(), None);
+        assert!(contract.get_paused_entry_points().is_empty());
+    }
+
+    #[test]
+    fn test_migrate_current_state_is_noop() {
+        let alice: AccountId = "alice.testnet".parse().unwrap();
+        set_context(&alice, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+
+        let mut contract = contract_with_relayer(&alice);
+        contract.signer_version = SignerVersion::V2;
+        contract.sign_btc(btc_tx_request(&alice), None, None);
+        env::state_write(&contract);
+        // Collections are flushed to storage when dropped, as at the end of a call
+        drop(contract);
+
+        let migrated = Contract::migrate();
+        assert_eq!(migrated.get_signer_version(), SignerVersion::V2);
+        assert_eq!(migrated.get_requests_for_account(alice, None, None).len(), 1);
+    }
+
+    #[test]
+    fn test_migrate_uses_stored_version() {
+        let alice: AccountId = "alice.testnet".parse().unwrap();
+        set_context(&alice, NearToken::from_yoctonear(0), vec![]);
+
+        // A V1 state tagged as another version isn't decoded with a layout that happens to fit
+        env::state_write(&ContractV1 { signer_account: "v1.signer-prod.testnet".parse().unwrap() });
+        env::storage_write(STATE_VERSION_KEY, &[CURRENT_STATE_VERSION]);
+        let result = std::panic::catch_unwind(Contract::migrate);
+        assert!(result.is_err());
+
+        env::storage_write(STATE_VERSION_KEY, &[CURRENT_STATE_VERSION + 1]);
+        let result = std::panic::catch_unwind(Contract::migrate);
+        assert!(result.is_err());
+
+        // Migrating tags the state with the current version
+        env::storage_remove(STATE_VERSION_KEY);
+        let contract = Contract::migrate();
+        assert_eq!(env::storage_read(STATE_VERSION_KEY), Some(vec![CURRENT_STATE_VERSION]));
+        assert_eq!(contract.get_owner_id(), env::current_account_id());
+    }
+}
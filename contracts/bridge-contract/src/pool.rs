@@ -0,0 +1,471 @@
+use crate::*;
+
+use admin::EntryPoint;
+use btc::{parse_btc_tx, BitcoinTransactionRequest};
+use events::BridgeEvent;
+use light_client::{ext_btc_light_client, ProofArgs};
+use near_sdk::{env, json_types::U128, near, serde_json, Gas, Promise, PromiseResult};
+use registry::RequestStatus;
+
+const CHAINS: [Chain; 4] = [Chain::Bitcoin, Chain::Evm, Chain::Solana, Chain::Near];
+
+const VERIFY_INCLUSION_GAS: Gas = Gas::from_tgas(20);
+const RECORD_DEPOSIT_CALLBACK_GAS: Gas = Gas::from_tgas(10);
+
+/// Blocks required on top of a BTC deposit, until the owner sets another depth.
+pub const DEFAULT_BTC_DEPOSIT_CONFIRMATIONS: u64 = 6;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolBalance {
+    pub chain: Chain,
+    #[schemars(with = "String")]
+    pub balance: U128,
+}
+
+/// BTC transaction paying an LP's pool, with the proof of its inclusion checked by the light client.
+///
+/// The credited amount is the value of output `output_index`, which must pay the LP's
+/// `btc_script_pubkey`. Hashes are hex in the byte order explorers display.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BtcDepositProof {
+    /// Raw transaction, hex-encoded.
+    pub tx: String,
+    pub output_index: u32,
+    pub block_hash: String,
+    /// Position of the transaction in its block.
+    pub tx_index: u64,
+    /// Sibling hashes from the transaction up to the block's merkle root.
+    pub merkle_proof: Vec<String>,
+}
+
+/// Liquidity taken from a pool for a swap payout, kept per request so resubmissions aren't debited twice.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolDebit {
+    pub account_id: AccountId,
+    pub chain: Chain,
+    #[schemars(with = "String")]
+    pub amount: U128,
+    /// Set once the amount went back to the pool after the request failed or expired.
+    pub released: bool,
+}
+
+/// Liquidity a swap payout takes from an LP's pool, `fee` of which is paid to the treasury.
+pub struct PoolPayout {
+    pub account_id: AccountId,
+    pub amount: u128,
+    pub fee: u128,
+}
+
+/// Satoshis leaving the pool at `pool_script_pubkey`: every input is spent and only the change comes back.
+///
+/// Fails if an input doesn't belong to the pool or if the outputs exceed the inputs.
+pub fn btc_outflow(tx_request: &BitcoinTransactionRequest, pool_script_pubkey: &str) -> Result<u128, String> {
+    if let Some(input) = tx_request
+        .inputs
+        .iter()
+        .find(|input| !input.script_pubkey.eq_ignore_ascii_case(pool_script_pubkey))
+    {
+        return Err(format!("Input {}:{} does not belong to the pool", input.txid, input.vout));
+    }
+
+    let total_input: u128 = tx_request.inputs.iter().map(|input| input.value as u128).sum();
+    let total_output: u128 = tx_request.outputs.iter().map(|output| output.value as u128).sum();
+    if total_output > total_input {
+        return Err("Outputs exceed inputs".to_string());
+    }
+
+    let change: u128 = tx_request
+        .outputs
+        .iter()
+        .filter(|output| output.script_pubkey.eq_ignore_ascii_case(pool_script_pubkey))
+        .map(|output| output.value as u128)
+        .sum();
+
+    Ok(total_input - change)
+}
+
+/// 32-byte hash displayed as `hash_hex`, in the byte order it is hashed in.
+fn parse_display_hash(field: &str, hash_hex: &str) -> [u8; 32] {
+    let mut hash: [u8; 32] = hex::decode(hash_hex)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .unwrap_or_else(|| env::panic_str(&format!("{} must be a 32-byte hex hash", field)));
+    hash.reverse();
+    hash
+}
+
+impl Contract {
+    pub(crate) fn pool_balance(&self, account_id: &AccountId, chain: Chain) -> u128 {
+        self.pool_balances
+            .get(&(account_id.clone(), chain))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub(crate) fn has_pool_balance(&self, account_id: &AccountId) -> bool {
+        CHAINS.iter().any(|chain| self.pool_balance(account_id, *chain) > 0)
+    }
+
+    fn set_pool_balance(&mut self, account_id: &AccountId, chain: Chain, balance: u128) {
+        self.pool_balances.insert((account_id.clone(), chain), balance);
+    }
+
+    /// Whether the payout signed by `request_id` already holds its liquidity. Resubmitting a
+    /// failed payout signs the same transaction, so it isn't debited again.
+    fn holds_pool_debit(&self, request_id: &RequestId) -> bool {
+        self.pool_debits.get(request_id).is_some_and(|debit| !debit.released)
+    }
+
+    /// Check that the pool can pay `amount` for `request_id`.
+    ///
+    /// This is the only check on the LP's liquidity, the kernel's `sufficient` flag is not trusted.
+    pub(crate) fn check_pool_debit(
+        &self,
+        account_id: &AccountId,
+        chain: Chain,
+        request_id: &RequestId,
+        amount: u128,
+    ) -> Result<(), String> {
+        let balance = self.pool_balance(account_id, chain);
+        if !self.holds_pool_debit(request_id) && amount > balance {
+            return Err(format!(
+                "Payout of {} exceeds the {} recorded in the {:?} pool of {}",
+                amount, balance, chain, account_id
+            ));
+        }
+        Ok(())
+    }
+
+    /// Take `amount` from the pool for the payout signed by `request_id`.
+    pub(crate) fn debit_pool(&mut self, account_id: &AccountId, chain: Chain, request_id: &RequestId, amount: u128) {
+        self.check_pool_debit(account_id, chain, request_id, amount)
+            .unwrap_or_else(|e| env::panic_str(&e));
+        if self.holds_pool_debit(request_id) {
+            return;
+        }
+
+        let balance = self.pool_balance(account_id, chain);
+        self.set_pool_balance(account_id, chain, balance - amount);
+        self.pool_debits.insert(request_id.clone(), PoolDebit {
+            account_id: account_id.clone(),
+            chain,
+            amount: U128(amount),
+            released: false,
+        });
+
+        BridgeEvent::PoolDebited {
+            account_id: account_id.clone(),
+            chain,
+            request_id: request_id.clone(),
+            amount: U128(amount),
+        }
+        .emit();
+    }
+
+    /// Return the liquidity held by the payout signed by `request_id`, if any, to its pool.
+    pub(crate) fn release_held_liquidity(&mut self, request_id: &RequestId) {
+        let Some(debit) = self.pool_debits.get_mut(request_id).filter(|debit| !debit.released) else {
+            return;
+        };
+        debit.released = true;
+        let PoolDebit { account_id, chain, amount, .. } = debit.clone();
+
+        let balance = self.pool_balance(&account_id, chain) + amount.0;
+        self.set_pool_balance(&account_id, chain, balance);
+        self.drop_fee(request_id);
+
+        BridgeEvent::PoolDebitReleased { account_id, chain, request_id: request_id.clone(), amount }.emit();
+    }
+
+    /// Payouts whose liquidity went back to the pool can't be signed anymore.
+    pub(crate) fn assert_pool_debit_held(&self, request_id: &RequestId) {
+        if self.pool_debits.get(request_id).is_some_and(|debit| debit.released) {
+            env::panic_str("The liquidity of this payout was released");
+        }
+    }
+}
+
+#[near]
+impl Contract {
+    /// Credit the pool of `account_id` with a BTC deposit, once the light client confirms the
+    /// transaction. Resolves to the new balance, or `None` if the proof is rejected.
+    pub fn record_deposit(&mut self, account_id: AccountId, proof: BtcDepositProof) -> Promise {
+        self.assert_not_paused(EntryPoint::RecordDeposit);
+        self.assert_permitted(EntryPoint::RecordDeposit, Chain::Bitcoin);
+        let light_client = self
+            .btc_light_client
+            .clone()
+            .unwrap_or_else(|| env::panic_str("No BTC light client is set"));
+
+        let tx = hex::decode(&proof.tx).unwrap_or_else(|_| env::panic_str("Invalid transaction hex"));
+        let parsed = parse_btc_tx(&tx).unwrap_or_else(|e| env::panic_str(&e));
+        let output = parsed
+            .outputs
+            .get(proof.output_index as usize)
+            .unwrap_or_else(|| env::panic_str(&format!("Transaction has no output {}", proof.output_index)));
+        if !output.script_pubkey.eq_ignore_ascii_case(&self.expect_lp(&account_id).btc_script_pubkey) {
+            env::panic_str(&format!("Output {} doesn't pay the pool of {}", proof.output_index, account_id));
+        }
+        let amount = U128(output.value as u128);
+        let args = ProofArgs {
+            tx_id: parse_display_hash("txid", &parsed.txid),
+            tx_block_blockhash: parse_display_hash("block_hash", &proof.block_hash),
+            tx_index: proof.tx_index,
+            merkle_proof: proof
+                .merkle_proof
+                .iter()
+                .map(|hash| parse_display_hash("merkle_proof", hash))
+                .collect(),
+            confirmations: self.btc_deposit_confirmations,
+        };
+
+        // Claimed until the proof resolves, so the same output can't be credited twice meanwhile
+        let initial_storage_usage = env::storage_usage();
+        if !self.proven_deposits.insert((Chain::Bitcoin, parsed.txid.clone(), proof.output_index)) {
+            env::panic_str("Deposit is already recorded");
+        }
+        // The pool's entry is created up front, so the callback stores nothing unpaid
+        let balance = self.pool_balance(&account_id, Chain::Bitcoin);
+        self.set_pool_balance(&account_id, Chain::Bitcoin, balance);
+        self.charge_storage(&env::predecessor_account_id(), initial_storage_usage);
+
+        ext_btc_light_client::ext(light_client)
+            .with_static_gas(VERIFY_INCLUSION_GAS)
+            .verify_transaction_inclusion(args)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(RECORD_DEPOSIT_CALLBACK_GAS)
+                    .record_deposit_callback(account_id, parsed.txid, proof.output_index, amount),
+            )
+    }
+
+    #[private]
+    pub fn record_deposit_callback(
+        &mut self,
+        account_id: AccountId,
+        tx_id: String,
+        index: u32,
+        amount: U128,
+    ) -> Option<U128> {
+        let rejection = match env::promise_result(0) {
+            PromiseResult::Successful(value) if serde_json::from_slice::<bool>(&value).unwrap_or(false) => None,
+            PromiseResult::Successful(_) => Some("Transaction is not confirmed in the given block"),
+            PromiseResult::Failed => Some("Light client call failed"),
+        };
+        let rejection = rejection.or_else(|| (!self.lps.contains_key(&account_id)).then_some("LP is not registered"));
+        if let Some(reason) = rejection {
+            // Released so the deposit can be proven again
+            self.proven_deposits.remove(&(Chain::Bitcoin, tx_id.clone(), index));
+            BridgeEvent::DepositRejected { account_id, chain: Chain::Bitcoin, tx_id, index, reason: reason.to_string() }
+                .emit();
+            return None;
+        }
+
+        let balance = self
+            .pool_balance(&account_id, Chain::Bitcoin)
+            .checked_add(amount.0)
+            .unwrap_or_else(|| env::panic_str("Pool balance overflow"));
+        self.set_pool_balance(&account_id, Chain::Bitcoin, balance);
+
+        BridgeEvent::DepositRecorded { account_id, chain: Chain::Bitcoin, tx_id, index, amount }.emit();
+        Some(U128(balance))
+    }
+
+    /// Prove BTC deposits with `light_client`, crediting them once `confirmations` blocks are on top.
+    pub fn set_btc_light_client(&mut self, light_client: AccountId, confirmations: u64) {
+        self.assert_owner();
+
+        self.btc_light_client = Some(light_client.clone());
+        self.btc_deposit_confirmations = confirmations;
+        BridgeEvent::BtcLightClientChanged { light_client, confirmations }.emit();
+    }
+
+    pub fn get_btc_light_client(&self) -> Option<AccountId> {
+        self.btc_light_client.clone()
+    }
+
+    pub fn get_btc_deposit_confirmations(&self) -> u64 {
+        self.btc_deposit_confirmations
+    }
+
+    /// Return the liquidity held by a failed or expired payout to its pool. The payout can't be
+    /// retried afterwards.
+    pub fn release_pool_debit(&mut self, request_id: RequestId) {
+        self.assert_owner_or_admin();
+
+        if !matches!(self.expect_request(&request_id).status, RequestStatus::Failed | RequestStatus::Expired) {
+            env::panic_str("Only the liquidity of failed or expired requests can be released");
+        }
+        match self.pool_debits.get(&request_id) {
+            None => env::panic_str("Request holds no pool liquidity"),
+            Some(debit) if debit.released => env::panic_str("Pool liquidity is already released"),
+            Some(_) => self.release_held_liquidity(&request_id),
+        }
+    }
+
+    pub fn get_pool_balance(&self, account_id: AccountId, chain: Chain) -> U128 {
+        U128(self.pool_balance(&account_id, chain))
+    }
+
+    pub fn get_pool_balances(&self, account_id: AccountId) -> Vec<PoolBalance> {
+        CHAINS
+            .iter()
+            .map(|chain| PoolBalance {
+                chain: *chain,
+                balance: U128(self.pool_balance(&account_id, *chain)),
+            })
+            .collect()
+    }
+
+    pub fn get_pool_debit(&self, request_id: RequestId) -> Option<PoolDebit> {
+        self.pool_debits.get(&request_id).cloned()
+    }
+
+    pub fn is_deposit_recorded(&self, chain: Chain, tx_id: String, index: u32) -> bool {
+        self.proven_deposits.contains(&(chain, tx_id.to_lowercase(), index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lp::RiskTier;
+    use near_sdk::NearToken;
+    use roles::Role;
+    use near_sdk::test_utils::get_logs;
+    use test_utils::{
+        btc_deposit_proof, btc_tx_request, prove_deposit, register_storage, set_context, sync_mpc_public_key_deriving,
+    };
+
+    const LP_PUBLIC_KEY: &str = "02b12224ecec8184dbff10316a889ebee9f7871bd6de358c5323fbecce9d84fd24";
+
+    fn contract_with_lp(owner: &AccountId, lp: &AccountId) -> Contract {
+        let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+        register_storage(&mut contract, owner);
+        register_storage(&mut contract, lp);
+        sync_mpc_public_key_deriving(&mut contract, lp, LP_PUBLIC_KEY);
+        contract.register_lp(lp.clone(), LP_PUBLIC_KEY.to_string(), vec![], RiskTier::Low);
+        contract.set_btc_light_client("btc-client.testnet".parse().unwrap(), 6);
+        contract
+    }
+
+    #[test]
+    fn test_btc_outflow() {
+        let tx_request = btc_tx_request(&"alice.near".parse().unwrap());
+        let pool_script_pubkey = &tx_request.inputs[0].script_pubkey;
+        // The payout and the fee leave the pool, the change comes back
+        assert_eq!(btc_outflow(&tx_request, pool_script_pubkey), Ok(430506 - 428854));
+        assert!(btc_outflow(&tx_request, "0014d3ae5a5de66aa44e7d5723b74e590340b3212f46").is_err());
+    }
+
+    #[test]
+    fn test_pool_accounting() {
+        let owner: AccountId = "alice.near".parse().unwrap();
+        let lp: AccountId = "lp.testnet".parse().unwrap();
+        set_context(&owner, NearToken::from_yoctonear(0), vec![]);
+
+        let mut contract = contract_with_lp(&owner, &lp);
+        let script_pubkey = contract.expect_lp(&lp).btc_script_pubkey.clone();
+        let proof = btc_deposit_proof(&script_pubkey, 1000, 1);
+        assert_eq!(prove_deposit(&mut contract, &owner, &lp, proof, true), Some(U128(1000)));
+
+        // A payout can't spend more than the pool holds
+        let request_id: RequestId = "request".to_string();
+        assert!(contract.check_pool_debit(&lp, Chain::Bitcoin, &request_id, 1001).is_err());
+
+        contract.debit_pool(&lp, Chain::Bitcoin, &request_id, 600);
+        contract.debit_pool(&lp, Chain::Bitcoin, &request_id, 600);
+        assert_eq!(contract.get_pool_balance(lp.clone(), Chain::Bitcoin), U128(400));
+        assert_eq!(contract.get_pool_debit(request_id).unwrap().amount, U128(600));
+
+        assert_eq!(contract.get_pool_balances(lp.clone()), vec![
+            PoolBalance { chain: Chain::Bitcoin, balance: U128(400) },
+            PoolBalance { chain: Chain::Evm, balance: U128(0) },
+            PoolBalance { chain: Chain::Solana, balance: U128(0) },
+            PoolBalance { chain: Chain::Near, balance: U128(0) },
+        ]);
+
+        // An LP can't leave with liquidity still recorded in its pools
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| contract.deregister_lp(lp.clone())));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_deposits_are_proven() {
+        let owner: AccountId = "alice.near".parse().unwrap();
+        let lp: AccountId = "lp.testnet".parse().unwrap();
+        set_context(&owner, NearToken::from_yoctonear(0), vec![]);
+
+        let mut contract = contract_with_lp(&owner, &lp);
+        let script_pubkey = contract.expect_lp(&lp).btc_script_pubkey.clone();
+        let proof = btc_deposit_proof(&script_pubkey, 1000, 1);
+        let tx_id = btc::parse_btc_tx(&hex::decode(&proof.tx).unwrap()).unwrap().txid;
+
+        // A proof the light client rejects credits nothing and frees the deposit to be proven again
+        assert_eq!(prove_deposit(&mut contract, &owner, &lp, proof.clone(), false), None);
+        assert!(get_logs().iter().any(|log| log.contains("\"event\":\"deposit_rejected\"")));
+        assert!(!contract.is_deposit_recorded(Chain::Bitcoin, tx_id.clone(), 0));
+        assert_eq!(contract.get_pool_balance(lp.clone(), Chain::Bitcoin), U128(0));
+
+        assert_eq!(prove_deposit(&mut contract, &owner, &lp, proof.clone(), true), Some(U128(1000)));
+        assert!(contract.is_deposit_recorded(Chain::Bitcoin, tx_id, 0));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.record_deposit(lp.clone(), proof.clone())
+        }));
+        assert!(result.is_err());
+
+        // Only outputs paying the LP's pool are credited
+        let other = btc_deposit_proof("0014d3ae5a5de66aa44e7d5723b74e590340b3212f46", 1000, 2);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.record_deposit(lp.clone(), other.clone())
+        }));
+        assert!(result.is_err());
+        let missing_output = BtcDepositProof { output_index: 1, ..btc_deposit_proof(&script_pubkey, 1000, 3) };
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.record_deposit(lp.clone(), missing_output.clone())
+        }));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_deposits_are_submitted_by_attesters() {
+        let owner: AccountId = "alice.near".parse().unwrap();
+        let lp: AccountId = "lp.testnet".parse().unwrap();
+        let relayer: AccountId = "relayer.testnet".parse().unwrap();
+        let attester: AccountId = "attester.testnet".parse().unwrap();
+        set_context(&owner, NearToken::from_yoctonear(0), vec![]);
+
+        let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+        for account_id in [&lp, &relayer, &attester] {
+            register_storage(&mut contract, account_id);
+        }
+        sync_mpc_public_key_deriving(&mut contract, &lp, LP_PUBLIC_KEY);
+        contract.register_lp(lp.clone(), LP_PUBLIC_KEY.to_string(), vec![], RiskTier::Low);
+        contract.grant_role(Role::Relayer, relayer.clone());
+        contract.grant_role(Role::Attester, attester.clone());
+        let proof = btc_deposit_proof(&contract.expect_lp(&lp).btc_script_pubkey, 1000, 1);
+
+        // Nothing is credited until a light client is set
+        set_context(&attester, NearToken::from_yoctonear(0), vec![]);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.record_deposit(lp.clone(), proof.clone())
+        }));
+        assert!(result.is_err());
+
+        set_context(&owner, NearToken::from_yoctonear(0), vec![]);
+        contract.set_btc_light_client("btc-client.testnet".parse().unwrap(), 3);
+        assert_eq!(contract.get_btc_deposit_confirmations(), 3);
+
+        // Relayers don't submit deposits, only attesters do
+        set_context(&relayer, NearToken::from_yoctonear(0), vec![]);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.record_deposit(lp.clone(), proof.clone())
+        }));
+        assert!(result.is_err());
+
+        assert_eq!(prove_deposit(&mut contract, &attester, &lp, proof, true), Some(U128(1000)));
+    }
+}
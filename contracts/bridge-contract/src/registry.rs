@@ -0,0 +1,355 @@
+use crate::*;
+
+use admin::EntryPoint;
+use ed25519::Ed25519TransactionRequest;
+use evm::EvmTransactionRequest;
+use events::BridgeEvent;
+use near_sdk::{env, near};
+use omni_transaction::bitcoin::bitcoin_transaction::BitcoinTransaction;
+use sign::KeyDerivation;
+use signer::SignatureResponse;
+
+/// Hex-encoded sha256 of the request's origin and payloads, see `request_id`.
+pub type RequestId = String;
+
+/// Pending requests without progress for this long can be marked as expired.
+pub const REQUEST_TTL_NS: u64 = 60 * 60 * 1_000_000_000;
+
+const DEFAULT_PAGE_LIMIT: u64 = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum RequestStatus {
+    Pending,
+    Signed,
+    Failed,
+    Expired,
+    /// Above the approval threshold, waiting for the guardians before being sent to the signer.
+    AwaitingApproval,
+    /// Vetoed by a guardian, it can't be submitted again.
+    Rejected,
+}
+
+/// Unsigned transaction and the data needed to finalize it once signatures arrive.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde", tag = "chain", rename_all = "snake_case")]
+pub enum PreparedPayload {
+    Bitcoin {
+        tx: BitcoinTransaction,
+        signer_public_key: String,
+    },
+    Evm {
+        tx_request: Box<EvmTransactionRequest>,
+        expected_address: String,
+    },
+    Ed25519 {
+        tx_request: Ed25519TransactionRequest,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SignatureRequest {
+    pub id: RequestId,
+    pub requester: AccountId,
+    pub chain: Chain,
+    /// Entry point that created the request, whose pause flag and permission also cover its retries.
+    pub entry_point: EntryPoint,
+    pub derivation: KeyDerivation,
+    pub payload: PreparedPayload,
+    /// Hash of each signed payload. Ed25519 payloads are signed as-is and only their sha256 is kept here.
+    pub sighashes: Vec<[u8; 32]>,
+    /// Verified signature for each sighash, kept across partial failures.
+    pub signatures: Vec<Option<SignatureResponse>>,
+    pub status: RequestStatus,
+    /// Bumped each time the request is sent to the signer, so callbacks of earlier attempts are told apart.
+    pub attempt: u32,
+    /// Broadcastable transaction hex, set once the request is signed.
+    pub signed_tx: Option<String>,
+    /// Why the request failed, if it did.
+    pub error: Option<String>,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl SignatureRequest {
+    /// Indexes of the sighashes that still have no signature.
+    pub fn missing_signatures(&self) -> Vec<u32> {
+        self.signatures
+            .iter()
+            .enumerate()
+            .filter(|(_, signature)| signature.is_none())
+            .map(|(i, _)| i as u32)
+            .collect()
+    }
+}
+
+/// Deterministic id: the same account asking for the same payloads under the same key
+/// always maps to the same request.
+pub fn request_id(
+    requester: &AccountId,
+    chain: Chain,
+    derivation: &KeyDerivation,
+    sighashes: &[[u8; 32]],
+) -> RequestId {
+    let preimage = borsh::to_vec(&(requester, chain, derivation, sighashes))
+        .expect("Failed to serialize request id preimage");
+    hex::encode(env::sha256(&preimage))
+}
+
+impl Contract {
+    /// Record a new pending request, or reset a failed or expired one with the same id.
+    pub(crate) fn register_request(
+        &mut self,
+        requester: AccountId,
+        chain: Chain,
+        entry_point: EntryPoint,
+        derivation: KeyDerivation,
+        payload: PreparedPayload,
+        sighashes: Vec<[u8; 32]>,
+    ) -> RequestId {
+        let id = request_id(&requester, chain, &derivation, &sighashes);
+        let now = env::block_timestamp();
+        // An expired attempt may still have its callback in flight, so resubmissions keep counting
+        let attempt = self.requests.get(&id).map_or(0, |request| request.attempt) + 1;
+
+        match self.requests.get(&id).map(|request| request.status) {
+            Some(RequestStatus::Pending) => env::panic_str("An identical request is already pending"),
+            Some(RequestStatus::Signed) => env::panic_str("An identical request is already signed"),
+            Some(RequestStatus::AwaitingApproval) => env::panic_str("An identical request is awaiting approval"),
+            Some(RequestStatus::Rejected) => env::panic_str("An identical request was rejected"),
+            Some(RequestStatus::Failed) | Some(RequestStatus::Expired) => {}
+            None => {
+                self.account_requests
+                    .entry(requester.clone())
+                    .or_insert_with(|| Vector::new(StorageKey::AccountRequestsInner { account_id: requester.clone() }))
+                    .push(id.clone());
+            }
+        }
+
+        BridgeEvent::SignRequested {
+            request_id: id.clone(),
+            requester: requester.clone(),
+            chain,
+            indexes: (0..sighashes.len() as u32).collect(),
+        }
+        .emit();
+
+        self.requests.insert(id.clone(), SignatureRequest {
+            id: id.clone(),
+            requester,
+            chain,
+            entry_point,
+            derivation,
+            payload,
+            signatures: vec![None; sighashes.len()],
+            sighashes,
+            status: RequestStatus::Pending,
+            attempt,
+            signed_tx: None,
+            error: None,
+            created_at: now,
+            updated_at: now,
+        });
+
+        id
+    }
+
+    pub(crate) fn expect_request(&self, request_id: &RequestId) -> &SignatureRequest {
+        self.requests
+            .get(request_id)
+            .unwrap_or_else(|| env::panic_str("Unknown signature request"))
+    }
+
+    pub(crate) fn record_signature(&mut self, request_id: &RequestId, index: u32, signature: SignatureResponse) {
+        let initial_storage_usage = env::storage_usage();
+        let request = self.requests.get_mut(request_id).expect("Unknown signature request");
+        request.signatures[index as usize] = Some(signature);
+        request.updated_at = env::block_timestamp();
+        let requester = request.requester.clone();
+        self.record_storage_usage(&requester, initial_storage_usage);
+
+        BridgeEvent::SignatureReceived { request_id: request_id.clone(), index }.emit();
+    }
+
+    pub(crate) fn mark_request_pending(&mut self, request_id: &RequestId) {
+        let request = self.requests.get_mut(request_id).expect("Unknown signature request");
+        request.status = RequestStatus::Pending;
+        request.attempt += 1;
+        request.error = None;
+        request.updated_at = env::block_timestamp();
+    }
+
+    pub(crate) fn mark_request_signed(&mut self, request_id: &RequestId, signed_tx: String) {
+        let initial_storage_usage = env::storage_usage();
+        let request = self.requests.get_mut(request_id).expect("Unknown signature request");
+        request.status = RequestStatus::Signed;
+        request.signed_tx = Some(signed_tx.clone());
+        request.error = None;
+        request.updated_at = env::block_timestamp();
+        let (requester, chain) = (request.requester.clone(), request.chain);
+        self.collect_fee(request_id);
+        self.record_storage_usage(&requester, initial_storage_usage);
+
+        BridgeEvent::TxFinalized { request_id: request_id.clone(), chain, signed_tx }.emit();
+    }
+
+    pub(crate) fn mark_request_awaiting_approval(&mut self, request_id: &RequestId) {
+        let request = self.requests.get_mut(request_id).expect("Unknown signature request");
+        request.status = RequestStatus::AwaitingApproval;
+        request.updated_at = env::block_timestamp();
+    }
+
+    pub(crate) fn mark_request_rejected(&mut self, request_id: &RequestId, error: String) {
+        let request = self.requests.get_mut(request_id).expect("Unknown signature request");
+        request.status = RequestStatus::Rejected;
+        request.error = Some(error);
+        request.updated_at = env::block_timestamp();
+    }
+
+    pub(crate) fn mark_request_expired(&mut self, request_id: &RequestId) {
+        let request = self.requests.get_mut(request_id).expect("Unknown signature request");
+        request.status = RequestStatus::Expired;
+        request.updated_at = env::block_timestamp();
+
+        BridgeEvent::RequestExpired { request_id: request_id.clone() }.emit();
+    }
+
+    pub(crate) fn mark_request_failed(&mut self, request_id: &RequestId, error: String) {
+        let initial_storage_usage = env::storage_usage();
+        let request = self.requests.get_mut(request_id).expect("Unknown signature request");
+        request.status = RequestStatus::Failed;
+        request.error = Some(error.clone());
+        request.updated_at = env::block_timestamp();
+        let requester = request.requester.clone();
+        self.record_storage_usage(&requester, initial_storage_usage);
+
+        BridgeEvent::SignFailed { request_id: request_id.clone(), error }.emit();
+    }
+}
+
+#[near]
+impl Contract {
+    pub fn get_request(&self, request_id: RequestId) -> Option<SignatureRequest> {
+        self.requests.get(&request_id).cloned()
+    }
+
+    /// Requests created by `account_id`, oldest first.
+    pub fn get_requests_for_account(
+        &self,
+        account_id: AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<SignatureRequest> {
+        let Some(ids) = self.account_requests.get(&account_id) else {
+            return vec![];
+        };
+
+        ids.iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(DEFAULT_PAGE_LIMIT) as usize)
+            .filter_map(|id| self.requests.get(id).cloned())
+            .collect()
+    }
+
+    /// Mark a request that has been pending without progress for longer than `REQUEST_TTL_NS` as expired.
+    pub fn expire_request(&mut self, request_id: RequestId) -> SignatureRequest {
+        let request = self.requests.get(&request_id).expect("Unknown signature request");
+
+        if request.status != RequestStatus::Pending {
+            env::panic_str("Only pending requests can expire");
+        }
+        if env::block_timestamp().saturating_sub(request.updated_at) < REQUEST_TTL_NS {
+            env::panic_str("Request has not expired yet");
+        }
+
+        self.mark_request_expired(&request_id);
+        self.expect_request(&request_id).clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::{test_utils::{get_logs, VMContextBuilder}, testing_env, NearToken, PromiseResult};
+    use sign::DEFAULT_SIGNATURE_DEPOSIT;
+    use test_utils::{btc_signature, btc_tx_request, contract_with_relayer, set_context, set_promise_results};
+
+    #[test]
+    fn test_btc_request_lifecycle() {
+        let alice: AccountId = "alice.testnet".parse().unwrap();
+        set_context(&alice, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+
+        let mut contract = contract_with_relayer(&alice);
+        contract.sign_btc(btc_tx_request(&alice), None, None);
+
+        let requests = contract.get_requests_for_account(alice.clone(), None, None);
+        assert_eq!(requests.len(), 1);
+        let request = &requests[0];
+        assert_eq!(request.status, RequestStatus::Pending);
+        assert_eq!(request.derivation.path, "alice.testnet");
+        assert_eq!(request.id, request_id(&alice, Chain::Bitcoin, &request.derivation, &request.sighashes));
+
+        // A failed signature is recorded on the request instead of reverting the callback
+        set_promise_results(&alice, vec![PromiseResult::Failed]);
+        assert_eq!(contract.sign_btc_callback(request.id.clone(), 1, vec![0], DEFAULT_SIGNATURE_DEPOSIT, NearToken::from_yoctonear(0)), None);
+        let failed = contract.get_request(request.id.clone()).unwrap();
+        assert_eq!(failed.status, RequestStatus::Failed);
+        assert!(failed.error.is_some());
+
+        // Resubmitting the same transaction reuses the failed request
+        set_context(&alice, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        contract.sign_btc(btc_tx_request(&alice), None, None);
+        assert_eq!(contract.get_request(request.id.clone()).unwrap().status, RequestStatus::Pending);
+        assert_eq!(contract.get_requests_for_account(alice.clone(), None, None).len(), 1);
+
+        let signature = near_sdk::serde_json::to_vec(&btc_signature(&alice)).unwrap();
+        set_promise_results(&alice, vec![PromiseResult::Successful(signature)]);
+        let tx_hex = contract.sign_btc_callback(request.id.clone(), 2, vec![0], DEFAULT_SIGNATURE_DEPOSIT, NearToken::from_yoctonear(0)).unwrap();
+
+        let signed = contract.get_request(request.id.clone()).unwrap();
+        assert_eq!(signed.status, RequestStatus::Signed);
+        assert_eq!(signed.signed_tx, Some(tx_hex));
+        assert_eq!(signed.error, None);
+        assert!(get_logs().iter().any(|log| log.starts_with("EVENT_JSON:") && log.contains("\"event\":\"tx_finalized\"")));
+
+        assert!(contract.get_requests_for_account(alice, Some(1), None).is_empty());
+    }
+
+    #[test]
+    fn test_callbacks_of_stale_attempts_are_ignored() {
+        let alice: AccountId = "alice.testnet".parse().unwrap();
+        set_context(&alice, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+
+        let mut contract = contract_with_relayer(&alice);
+        contract.sign_btc(btc_tx_request(&alice), None, None);
+        let request_id = contract.get_requests_for_account(alice.clone(), None, None)[0].id.clone();
+        assert_eq!(contract.get_request(request_id.clone()).unwrap().attempt, 1);
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(alice.clone())
+            .block_timestamp(REQUEST_TTL_NS)
+            .build());
+        contract.expire_request(request_id.clone());
+
+        // The signature of the expired attempt arrives late
+        let signature = near_sdk::serde_json::to_vec(&btc_signature(&alice)).unwrap();
+        set_promise_results(&alice, vec![PromiseResult::Successful(signature.clone())]);
+        assert_eq!(contract.sign_btc_callback(request_id.clone(), 1, vec![0], DEFAULT_SIGNATURE_DEPOSIT, NearToken::from_yoctonear(0)), None);
+        let expired = contract.get_request(request_id.clone()).unwrap();
+        assert_eq!(expired.status, RequestStatus::Expired);
+        assert_eq!(expired.missing_signatures(), vec![0]);
+
+        // Once retried, only the results of the new attempt count
+        set_context(&alice, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        contract.retry_btc_signature(request_id.clone(), vec![0]);
+        set_promise_results(&alice, vec![PromiseResult::Failed]);
+        assert_eq!(contract.sign_btc_callback(request_id.clone(), 1, vec![0], DEFAULT_SIGNATURE_DEPOSIT, NearToken::from_yoctonear(0)), None);
+        let retried = contract.get_request(request_id.clone()).unwrap();
+        assert_eq!((retried.status, retried.attempt, retried.error), (RequestStatus::Pending, 2, None));
+
+        set_promise_results(&alice, vec![PromiseResult::Successful(signature)]);
+        assert!(contract.sign_btc_callback(request_id.clone(), 2, vec![0], DEFAULT_SIGNATURE_DEPOSIT, NearToken::from_yoctonear(0)).is_some());
+        assert_eq!(contract.get_request(request_id).unwrap().status, RequestStatus::Signed);
+    }
+}
@@ -0,0 +1,238 @@
+use crate::*;
+
+use admin::EntryPoint;
+use events::BridgeEvent;
+use near_sdk::{env, near, store::IterableSet};
+
+const DEFAULT_PAGE_LIMIT: u64 = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum Role {
+    /// Manages relayers and integrators and can pause entry points.
+    Admin,
+    /// Off-chain services submitting transactions on behalf of users.
+    Relayer,
+    /// Contracts building on top of the bridge.
+    Integrator,
+    /// Approves or rejects transfers above the approval threshold.
+    Guardian,
+    /// Submits the proofs of the deposits credited to LP pools.
+    Attester,
+}
+
+impl Role {
+    pub const ALL: [Role; 5] = [Role::Admin, Role::Relayer, Role::Integrator, Role::Guardian, Role::Attester];
+}
+
+/// Entry points a role may call, and the chains it may sign for through them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RolePermissions {
+    pub entry_points: Vec<EntryPoint>,
+    pub chains: Vec<Chain>,
+}
+
+impl RolePermissions {
+    pub fn allows(&self, entry_point: EntryPoint, chain: Chain) -> bool {
+        self.entry_points.contains(&entry_point) && self.chains.contains(&chain)
+    }
+}
+
+/// Permissions every role starts with, until the owner changes them.
+pub fn default_role_permissions() -> LookupMap<Role, RolePermissions> {
+    let all_chains = vec![Chain::Bitcoin, Chain::Evm, Chain::Solana, Chain::Near];
+    let signing = vec![EntryPoint::SignBtc, EntryPoint::SignEvm, EntryPoint::SignEd25519];
+
+    let mut role_permissions = LookupMap::new(StorageKey::RolePermissions);
+    role_permissions.insert(Role::Admin, RolePermissions {
+        entry_points: [signing.clone(), vec![EntryPoint::SwapBtcKrnl]].concat(),
+        chains: all_chains.clone(),
+    });
+    role_permissions.insert(Role::Relayer, RolePermissions {
+        entry_points: signing,
+        chains: all_chains,
+    });
+    role_permissions.insert(Role::Integrator, RolePermissions {
+        entry_points: vec![EntryPoint::SwapBtcKrnl],
+        chains: vec![Chain::Bitcoin],
+    });
+    role_permissions.insert(Role::Attester, RolePermissions {
+        entry_points: vec![EntryPoint::RecordDeposit],
+        chains: vec![Chain::Bitcoin],
+    });
+    role_permissions
+}
+
+impl Contract {
+    pub(crate) fn has_role_internal(&self, role: Role, account_id: &AccountId) -> bool {
+        self.role_members
+            .get(&role)
+            .is_some_and(|members| members.contains(account_id))
+    }
+
+    pub(crate) fn grant_role_internal(&mut self, role: Role, account_id: AccountId) -> bool {
+        self.role_members
+            .entry(role)
+            .or_insert_with(|| IterableSet::new(StorageKey::RoleMembersInner { role }))
+            .insert(account_id)
+    }
+
+    pub(crate) fn assert_owner_or_admin(&self) {
+        let caller = env::predecessor_account_id();
+        if caller != self.owner_id && !self.has_role_internal(Role::Admin, &caller) {
+            env::panic_str("Only the owner or an admin can call this method");
+        }
+    }
+
+    /// Only the owner manages admins, guardians and attesters, admins manage the other roles.
+    fn assert_can_manage(&self, role: Role) {
+        match role {
+            Role::Admin | Role::Guardian | Role::Attester => self.assert_owner(),
+            Role::Relayer | Role::Integrator => self.assert_owner_or_admin(),
+        }
+    }
+
+    /// The owner may call everything, other callers need a role allowing `entry_point` on `chain`.
+    pub(crate) fn assert_permitted(&self, entry_point: EntryPoint, chain: Chain) {
+        let caller = env::predecessor_account_id();
+        if caller == self.owner_id {
+            return;
+        }
+
+        let permitted = Role::ALL.iter().any(|role| {
+            self.has_role_internal(*role, &caller)
+                && self
+                    .role_permissions
+                    .get(role)
+                    .is_some_and(|permissions| permissions.allows(entry_point, chain))
+        });
+        if !permitted {
+            env::panic_str(&format!("{} is not allowed to call {:?} for {:?}", caller, entry_point, chain));
+        }
+    }
+}
+
+#[near]
+impl Contract {
+    pub fn grant_role(&mut self, role: Role, account_id: AccountId) {
+        self.assert_can_manage(role);
+
+        if self.grant_role_internal(role, account_id.clone()) {
+            BridgeEvent::RoleGranted { role, account_id, by: env::predecessor_account_id() }.emit();
+        }
+    }
+
+    pub fn revoke_role(&mut self, role: Role, account_id: AccountId) {
+        self.assert_can_manage(role);
+
+        let removed = self
+            .role_members
+            .get_mut(&role)
+            .is_some_and(|members| members.remove(&account_id));
+        if removed {
+            BridgeEvent::RoleRevoked { role, account_id, by: env::predecessor_account_id() }.emit();
+        }
+    }
+
+    pub fn set_role_permissions(&mut self, role: Role, permissions: RolePermissions) {
+        self.assert_owner();
+
+        self.role_permissions.insert(role, permissions.clone());
+        BridgeEvent::RolePermissionsChanged { role, permissions }.emit();
+    }
+
+    pub fn get_role_permissions(&self, role: Role) -> RolePermissions {
+        self.role_permissions.get(&role).cloned().unwrap_or_default()
+    }
+
+    pub fn has_role(&self, role: Role, account_id: AccountId) -> bool {
+        self.has_role_internal(role, &account_id)
+    }
+
+    pub fn get_roles(&self, account_id: AccountId) -> Vec<Role> {
+        Role::ALL
+            .into_iter()
+            .filter(|role| self.has_role_internal(*role, &account_id))
+            .collect()
+    }
+
+    pub fn get_role_members(&self, role: Role, from_index: Option<u64>, limit: Option<u64>) -> Vec<AccountId> {
+        let Some(members) = self.role_members.get(&role) else {
+            return vec![];
+        };
+
+        members
+            .iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(DEFAULT_PAGE_LIMIT) as usize)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::NearToken;
+    use sign::DEFAULT_SIGNATURE_DEPOSIT;
+    use test_utils::{btc_tx_request, register_storage, set_context, sync_test_mpc_public_key};
+
+    #[test]
+    fn test_role_management() {
+        let owner: AccountId = "alice.near".parse().unwrap();
+        let admin: AccountId = "admin.testnet".parse().unwrap();
+        let relayer: AccountId = "relayer.testnet".parse().unwrap();
+        set_context(&owner, NearToken::from_yoctonear(0), vec![]);
+
+        let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+        contract.grant_role(Role::Admin, admin.clone());
+
+        // Admins manage relayers and integrators, but not other admins or attesters
+        set_context(&admin, NearToken::from_yoctonear(0), vec![]);
+        contract.grant_role(Role::Relayer, relayer.clone());
+        for role in [Role::Admin, Role::Attester] {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                contract.grant_role(role, relayer.clone())
+            }));
+            assert!(result.is_err());
+        }
+
+        assert_eq!(contract.get_role_members(Role::Relayer, None, None), vec![relayer.clone()]);
+        assert_eq!(contract.get_roles(relayer.clone()), vec![Role::Relayer]);
+        assert!(contract.has_role(Role::Admin, admin.clone()));
+
+        contract.revoke_role(Role::Relayer, relayer.clone());
+        assert!(contract.get_role_members(Role::Relayer, None, None).is_empty());
+        assert!(contract.get_roles(relayer).is_empty());
+    }
+
+    #[test]
+    fn test_signing_permissions() {
+        let owner: AccountId = "alice.near".parse().unwrap();
+        let integrator: AccountId = "integrator.testnet".parse().unwrap();
+        set_context(&owner, NearToken::from_yoctonear(0), vec![]);
+
+        let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+        contract.grant_role(Role::Integrator, integrator.clone());
+        register_storage(&mut contract, &integrator);
+        sync_test_mpc_public_key(&mut contract);
+
+        // Integrators may only swap by default
+        set_context(&integrator, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.sign_btc(btc_tx_request(&integrator), None, None)
+        }));
+        assert!(result.is_err());
+
+        set_context(&owner, NearToken::from_yoctonear(0), vec![]);
+        contract.set_role_permissions(Role::Integrator, RolePermissions {
+            entry_points: vec![EntryPoint::SignBtc],
+            chains: vec![Chain::Bitcoin],
+        });
+
+        set_context(&integrator, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        contract.sign_btc(btc_tx_request(&integrator), None, None);
+        assert_eq!(contract.get_requests_for_account(integrator, None, None).len(), 1);
+    }
+}
@@ -1,133 +1,901 @@
 use crate::*;
 
-use btc::{BitcoinTransactionRequest, PreparedBitcoinTransaction};
+use btc::{build_signed_btc_tx, BitcoinTransactionRequest, PreparedBitcoinTransaction};
+use ed25519::Ed25519TransactionRequest;
 use evm::EvmTransactionRequest;
-use near_sdk::{env, log, near, Gas, NearToken, Promise, PromiseError};
-use signer::{SignRequest, SignResult, ext_signer};
+use k256::AffinePoint;
+use near_sdk::{
+    borsh::{BorshDeserialize, BorshSerialize},
+    env,
+    json_types::U128,
+    log, near,
+    serde::{Deserialize, Serialize},
+    Gas, NearToken, Promise, PromiseOrValue, PromiseResult,
+};
+use schemars::JsonSchema;
+use admin::EntryPoint;
+use events::BridgeEvent;
+use limits::btc_spent;
+use pool::PoolPayout;
+use registry::{PreparedPayload, RequestId, RequestStatus, SignatureRequest};
+use signer::{
+    ext_signer, ext_signer_v2, Payload, SignRequest, SignRequestV2, SignResult, SignatureResponse, SignerVersion,
+};
+use verify::{normalize_s, parse_public_key_hex, verify_signature};
 
 const SIGN_GAS: Gas = Gas::from_tgas(100);
 const SWAP_CALLBACK_GAS: Gas = Gas::from_tgas(10);
+/// Extra callback gas for recovering and checking each signature.
+const VERIFY_GAS_PER_SIGNATURE: Gas = Gas::from_tgas(15);
+const SIGNATURE_DEPOSIT_GAS: Gas = Gas::from_tgas(10);
+const SIGNATURE_DEPOSIT_CALLBACK_GAS: Gas = Gas::from_tgas(5);
+
+/// Deposit attached to each signature until `sync_signature_deposit` or `set_signature_deposit`
+/// is called. The signer rejects requests below its current deposit.
+pub const DEFAULT_SIGNATURE_DEPOSIT: NearToken = NearToken::from_yoctonear(1);
+
+/// Key version requested from the signer when the caller does not pick one.
+pub const DEFAULT_KEY_VERSION: u32 = 0;
+
+/// Separates the owning account from the caller-chosen suffix of a derivation path.
+/// Account ids can't contain `/`, so `alice.near/btc/0` always belongs to `alice.near`.
+pub const PATH_SEPARATOR: char = '/';
+
+/// Derivation path and key version forwarded to the MPC signer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct KeyDerivation {
+    pub path: String,
+    pub key_version: u32,
+}
+
+impl KeyDerivation {
+    /// Resolve the path requested by `owner` into a namespaced derivation.
+    ///
+    /// All keys are derived from the bridge account, so paths are namespaced by the account
+    /// they belong to: `owner` or `owner/<suffix>`. Without an explicit path the owner's
+    /// account id is used, giving every caller its own derived address.
+    pub fn for_owner(owner: &AccountId, path: Option<String>, key_version: Option<u32>) -> Self {
+        let path = path.unwrap_or_else(|| owner.to_string());
+
+        let is_namespaced = match path.strip_prefix(owner.as_str()) {
+            Some("") => true,
+            Some(suffix) => suffix.starts_with(PATH_SEPARATOR) && suffix.len() > 1,
+            None => false,
+        };
+        if !is_namespaced {
+            env::panic_str(&format!(
+                "Derivation path must be `{}` or start with `{}{}`",
+                owner, owner, PATH_SEPARATOR
+            ));
+        }
+
+        Self {
+            path,
+            key_version: key_version.unwrap_or(DEFAULT_KEY_VERSION),
+        }
+    }
+}
+
+/// Decode a secp256k1 signature returned by the signer, normalized and checked against `expected`.
+fn verified_signature(
+    version: SignerVersion,
+    value: &[u8],
+    payload: &[u8; 32],
+    expected: &AffinePoint,
+) -> Result<SignResult, String> {
+    let signature = SignatureResponse::from_json(version, value)?.into_secp256k1()?;
+    let signature = normalize_s(signature).map_err(|e| e.to_string())?;
+    verify_signature(payload, &signature, expected).map_err(|e| e.to_string())?;
+
+    Ok(signature)
+}
+
+/// Send `amount` back to `account_id`. Deposits of failed sign calls are returned to this
+/// contract by the runtime, so callbacks forward them together with any unused deposit.
+pub(crate) fn refund_deposit(account_id: &AccountId, amount: NearToken) {
+    if amount.is_zero() {
+        return;
+    }
+
+    log!("Refunding {} to {}", amount, account_id);
+    Promise::new(account_id.clone()).transfer(amount);
+}
 
-#[near]
 impl Contract {
-    fn promise_sign(&self, hash: [u8; 32], deposit: NearToken) -> Promise {
-        // TODO: Should be customizable by the caller
-        let sign_request = SignRequest::new(
-            hash,
-            "".to_string(),
-            0
+    /// V2 signers derive keys from the root key of a domain and have no key versions, so only the
+    /// default one can be requested from them.
+    pub(crate) fn check_key_version(&self, derivation: &KeyDerivation) -> Result<(), String> {
+        if self.signer_version == SignerVersion::V2 && derivation.key_version != DEFAULT_KEY_VERSION {
+            return Err(format!("Key version {} is not supported by v2 signers", derivation.key_version));
+        }
+        Ok(())
+    }
+
+    /// Register and dispatch a BTC request for `requester`, once the entry point has checked the caller.
+    ///
+    /// Swap payouts pass the liquidity they take from the LP's pool as `pool_payout`, and are
+    /// retried under `SwapBtcKrnl` while everything else falls under `SignBtc`. Transfers
+    /// above the approval threshold pass their value as `approval_amount` and wait for the
+    /// guardians instead of being sent to the signer.
+    pub(crate) fn internal_sign_btc(
+        &mut self,
+        requester: AccountId,
+        derivation: KeyDerivation,
+        prepared_bitcoin_transaction: PreparedBitcoinTransaction,
+        signer_public_key: String,
+        pool_payout: Option<PoolPayout>,
+        approval_amount: Option<u128>,
+    ) -> PromiseOrValue<Option<String>> {
+        let leftover_deposit = self.leftover_deposit(prepared_bitcoin_transaction.sighashes.len() as u64);
+        let input_indexes = (0..prepared_bitcoin_transaction.sighashes.len() as u32).collect();
+        let entry_point = if pool_payout.is_some() { EntryPoint::SwapBtcKrnl } else { EntryPoint::SignBtc };
+        let request_id = self.register_request(
+            requester,
+            Chain::Bitcoin,
+            entry_point,
+            derivation,
+            PreparedPayload::Bitcoin {
+                tx: prepared_bitcoin_transaction.tx,
+                signer_public_key,
+            },
+            prepared_bitcoin_transaction.sighashes,
         );
+        if let Some(PoolPayout { account_id, amount, fee }) = pool_payout {
+            self.debit_pool(&account_id, Chain::Bitcoin, &request_id, amount);
+            self.hold_fee(Chain::Bitcoin, &request_id, fee);
+        }
 
-        ext_signer::ext(self.signer_account.clone())
-            .with_attached_deposit(deposit)
-            .with_static_gas(SIGN_GAS)
-            .sign(sign_request)
+        if let Some(amount) = approval_amount {
+            self.queue_approval(&request_id, Chain::Bitcoin, amount, env::attached_deposit());
+            return PromiseOrValue::Value(None);
+        }
+        self.dispatch_btc_signatures(request_id, input_indexes, leftover_deposit).into()
     }
 
-    #[private]
-    #[payable]
-    pub fn sign_btc(&mut self, tx_request: BitcoinTransactionRequest) -> Promise {
-        let input_utxos_len = tx_request.inputs.len() as u128;
-        let sign_deposit = env::attached_deposit().saturating_div(input_utxos_len);
+    /// Send the only payload of an EVM request to the signer.
+    pub(crate) fn dispatch_evm_signature(&self, request_id: RequestId, leftover_deposit: NearToken) -> Promise {
+        let deposit_per_signature = self.signature_deposit;
+        let request = self.expect_request(&request_id);
+        let payload = Payload::Ecdsa(hex::encode(request.sighashes[0]));
 
-        let prepared_bitcoin_transaction = self.prepare_btc_tx(tx_request.clone());
-        let mut combined_promise = self.promise_sign(prepared_bitcoin_transaction.sighashes[0], sign_deposit);
+        self.promise_sign(payload, &request.derivation, deposit_per_signature).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(SWAP_CALLBACK_GAS.saturating_add(VERIFY_GAS_PER_SIGNATURE))
+                .sign_evm_callback(request_id, request.attempt, deposit_per_signature, leftover_deposit)
+        )
+    }
+}
+
+#[near]
+impl Contract {
+    /// Ask the signer for a signature over `payload`, in the format of the configured `signer_version`.
+    fn promise_sign(&self, payload: Payload, derivation: &KeyDerivation, deposit: NearToken) -> Promise {
+        match self.signer_version {
+            SignerVersion::V1 => {
+                let Payload::Ecdsa(hash) = payload else {
+                    env::panic_str("V1 signers only support ECDSA payloads");
+                };
+                let hash: [u8; 32] = hex::decode(hash)
+                    .ok()
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .unwrap_or_else(|| env::panic_str("ECDSA payload must be a 32-byte hash"));
+                let sign_request = SignRequest::new(
+                    hash,
+                    derivation.path.clone(),
+                    derivation.key_version
+                );
+
+                ext_signer::ext(self.signer_account.clone())
+                    .with_attached_deposit(deposit)
+                    .with_static_gas(SIGN_GAS)
+                    .sign(sign_request)
+            }
+            SignerVersion::V2 => {
+                // Requests made before switching to a v2 signer may still carry another key version
+                self.check_key_version(derivation).unwrap_or_else(|e| env::panic_str(&e));
+                let sign_request = SignRequestV2::new(payload, derivation.path.clone());
+
+                ext_signer_v2::ext(self.signer_account.clone())
+                    .with_attached_deposit(deposit)
+                    .with_static_gas(SIGN_GAS)
+                    .sign(sign_request)
+            }
+        }
+    }
+
+    /// Deposit left once `signatures` signatures are paid for. Panics if the attached deposit
+    /// doesn't cover them, before any promise is created.
+    fn leftover_deposit(&self, signatures: u64) -> NearToken {
+        let required = self.signature_deposit.saturating_mul(signatures as u128);
+        let attached = env::attached_deposit();
+
+        if attached < required {
+            env::panic_str(&format!(
+                "Attached deposit {} is below the {} required for {} signatures",
+                attached, required, signatures
+            ));
+        }
+
+        attached.saturating_sub(required)
+    }
+
+    /// Read the result of a request's only sign promise and refund `leftover_deposit`, along with
+    /// the signature's deposit if the signer failed. Returns `None` once the failure is recorded.
+    fn single_signature_response(
+        &mut self,
+        request: &SignatureRequest,
+        deposit_per_signature: NearToken,
+        leftover_deposit: NearToken,
+    ) -> Option<SignatureResponse> {
+        let value = match env::promise_result(0) {
+            PromiseResult::Successful(value) => value,
+            PromiseResult::Failed => {
+                refund_deposit(&request.requester, leftover_deposit.saturating_add(deposit_per_signature));
+                self.mark_request_failed(&request.id, "Failed to get signature from signer".to_string());
+                return None;
+            }
+        };
+        refund_deposit(&request.requester, leftover_deposit);
+
+        match SignatureResponse::from_json(self.signer_version, &value) {
+            Ok(response) => Some(response),
+            Err(e) => {
+                self.mark_request_failed(&request.id, e);
+                None
+            }
+        }
+    }
 
-        for sighash in prepared_bitcoin_transaction.sighashes.iter().skip(1) {
-            let sign_promise = self.promise_sign(*sighash, sign_deposit);
-            combined_promise = combined_promise.and(sign_promise);
+    /// Results of an attempt that has expired or been superseded are dropped, only refunding the
+    /// requester's `leftover_deposit` and the deposits the signer returned. Returns whether it did.
+    fn ignore_stale_attempt(
+        &self,
+        request: &SignatureRequest,
+        attempt: u32,
+        promises_len: u64,
+        deposit_per_signature: NearToken,
+        leftover_deposit: NearToken,
+    ) -> bool {
+        if request.status == RequestStatus::Pending && request.attempt == attempt {
+            return false;
         }
 
-        let promises_len = prepared_bitcoin_transaction.sighashes.len() as u64;
+        let failed = (0..promises_len)
+            .filter(|index| matches!(env::promise_result(*index), PromiseResult::Failed))
+            .count();
+        refund_deposit(
+            &request.requester,
+            leftover_deposit.saturating_add(deposit_per_signature.saturating_mul(failed as u128)),
+        );
+        log!("Ignoring signatures of attempt {} of request {}", attempt, request.id);
+        true
+    }
+
+    /// Request signatures for `input_indexes` of a registered BTC request and finalize it in
+    /// `sign_btc_callback`, which refunds `leftover_deposit` to the requester.
+    pub(crate) fn dispatch_btc_signatures(
+        &self,
+        request_id: RequestId,
+        input_indexes: Vec<u32>,
+        leftover_deposit: NearToken,
+    ) -> Promise {
+        let deposit_per_signature = self.signature_deposit;
+        let request = self.expect_request(&request_id);
+
+        let combined_promise = input_indexes
+            .iter()
+            .map(|index| {
+                let payload = Payload::Ecdsa(hex::encode(request.sighashes[*index as usize]));
+                self.promise_sign(payload, &request.derivation, deposit_per_signature)
+            })
+            .reduce(|combined, sign_promise| combined.and(sign_promise))
+            .unwrap_or_else(|| env::panic_str("No signatures to request"));
+
+        let promises_len = input_indexes.len() as u64;
         combined_promise.then(
             Self::ext(env::current_account_id())
-                .with_static_gas(SWAP_CALLBACK_GAS.into())
-                .sign_btc_callback(
-                    prepared_bitcoin_transaction,
-                    tx_request.signer_public_key,
-                    promises_len
-                )
+                .with_static_gas(SWAP_CALLBACK_GAS.saturating_add(VERIFY_GAS_PER_SIGNATURE.saturating_mul(promises_len)))
+                .sign_btc_callback(request_id, request.attempt, input_indexes, deposit_per_signature, leftover_deposit)
         )
     }
 
+    /// Sign every input of `tx_request` with the caller's derived key.
+    #[payable]
+    pub fn sign_btc(
+        &mut self,
+        tx_request: BitcoinTransactionRequest,
+        path: Option<String>,
+        key_version: Option<u32>,
+    ) -> PromiseOrValue<Option<String>> {
+        self.assert_not_paused(EntryPoint::SignBtc);
+        self.assert_permitted(EntryPoint::SignBtc, Chain::Bitcoin);
+
+        let requester = env::predecessor_account_id();
+        let derivation = KeyDerivation::for_owner(&requester, path, key_version);
+        self.check_key_version(&derivation).unwrap_or_else(|e| env::panic_str(&e));
+        // Signatures are checked against the key the signer derives, not one the caller picked
+        let point = self.derived_public_key(&derivation.path);
+        let signer_public_key = hex::encode(derivation::compressed_public_key(&point));
+        if !tx_request.signer_public_key.eq_ignore_ascii_case(&signer_public_key) {
+            env::panic_str(&format!(
+                "signer_public_key must be {}, the key derived for {}",
+                signer_public_key, derivation.path
+            ));
+        }
+
+        let initial_storage_usage = env::storage_usage();
+        let prepared_bitcoin_transaction = self.prepare_btc_tx(tx_request.clone());
+        let mut approval_amount = None;
+        if self.tracks_value(Chain::Bitcoin, None) {
+            let own_script_pubkey = hex::encode(derivation::p2wpkh_script_pubkey(&point));
+            let request_id =
+                registry::request_id(&requester, Chain::Bitcoin, &derivation, &prepared_bitcoin_transaction.sighashes);
+            let value = btc_spent(&tx_request, &own_script_pubkey);
+            self.record_outflow(&request_id, Chain::Bitcoin, None, value);
+            approval_amount = self.requires_approval(&request_id, Chain::Bitcoin, value).then_some(value);
+        }
+        let result = self.internal_sign_btc(
+            requester.clone(),
+            derivation,
+            prepared_bitcoin_transaction,
+            signer_public_key,
+            None,
+            approval_amount,
+        );
+        self.charge_storage(&requester, initial_storage_usage);
+        result
+    }
+
+    /// Request the signatures still missing from a failed or expired BTC request.
+    ///
+    /// Signatures obtained by earlier attempts are kept, so only `input_indexes` are
+    /// signed again and the transaction is finalized once every input is signed.
+    #[payable]
+    pub fn retry_btc_signature(&mut self, request_id: RequestId, input_indexes: Vec<u32>) -> Promise {
+        let request = self.expect_request(&request_id);
+        self.assert_not_paused(request.entry_point);
+        self.assert_permitted(request.entry_point, Chain::Bitcoin);
+
+        if request.requester != env::predecessor_account_id() {
+            env::panic_str("Only the requester can retry a signature request");
+        }
+        if request.chain != Chain::Bitcoin {
+            env::panic_str("Not a bitcoin signature request");
+        }
+        if !matches!(request.status, RequestStatus::Failed | RequestStatus::Expired) {
+            env::panic_str("Only failed or expired requests can be retried");
+        }
+        self.assert_pool_debit_held(&request_id);
+        self.assert_approved(&request_id);
+        self.check_key_version(&request.derivation).unwrap_or_else(|e| env::panic_str(&e));
+
+        let missing = request.missing_signatures();
+        let mut unique_indexes = input_indexes.clone();
+        unique_indexes.sort_unstable();
+        unique_indexes.dedup();
+        if input_indexes.is_empty()
+            || unique_indexes.len() != input_indexes.len()
+            || input_indexes.iter().any(|index| !missing.contains(index))
+        {
+            env::panic_str(&format!("Input indexes must be distinct and among the missing ones: {:?}", missing));
+        }
+
+        let leftover_deposit = self.leftover_deposit(input_indexes.len() as u64);
+        let initial_storage_usage = env::storage_usage();
+        self.mark_request_pending(&request_id);
+        self.charge_storage(&env::predecessor_account_id(), initial_storage_usage);
+        BridgeEvent::SignRequested {
+            request_id: request_id.clone(),
+            requester: env::predecessor_account_id(),
+            chain: Chain::Bitcoin,
+            indexes: input_indexes.clone(),
+        }
+        .emit();
+        self.dispatch_btc_signatures(request_id, input_indexes, leftover_deposit)
+    }
+
+    /// Store every valid signature, then finalize once all inputs are signed. Failures are
+    /// recorded on the request rather than panicking, so they stay visible through `get_request`.
     #[private]
     pub fn sign_btc_callback(
         &mut self,
-        prepared_bitcoin_transaction: PreparedBitcoinTransaction,
-        signer_public_key: String,
-        num_signatures: u64,
-    ) -> String {
-        let mut signatures = Vec::with_capacity(num_signatures as usize);
-
-        for i in 0..num_signatures {
-            match env::promise_result(i) {
-                near_sdk::PromiseResult::Successful(value) => {
-                    if let Ok(signature) = near_sdk::serde_json::from_slice::<SignResult>(&value) {
-                        log!("Got signature from signer {:?}", signature);
-                        signatures.push(signature);
-                    } else {
-                        log!("Failed to deserialize signature");
-                        panic!("Failed to deserialize signature");
-                    }
+        request_id: RequestId,
+        attempt: u32,
+        input_indexes: Vec<u32>,
+        deposit_per_signature: NearToken,
+        leftover_deposit: NearToken,
+    ) -> Option<String> {
+        let request = self.expect_request(&request_id).clone();
+        let promises_len = input_indexes.len() as u64;
+        if self.ignore_stale_attempt(&request, attempt, promises_len, deposit_per_signature, leftover_deposit) {
+            return None;
+        }
+        let PreparedPayload::Bitcoin { tx, signer_public_key } = request.payload else {
+            env::panic_str("Not a bitcoin signature request");
+        };
+
+        let expected_public_key = parse_public_key_hex(&signer_public_key).map_err(|e| e.to_string());
+        let mut refund = leftover_deposit;
+        let mut errors = Vec::new();
+        for (promise_index, input_index) in input_indexes.iter().enumerate() {
+            let sighash = &request.sighashes[*input_index as usize];
+            let signature = match env::promise_result(promise_index as u64) {
+                PromiseResult::Successful(value) => expected_public_key
+                    .clone()
+                    .and_then(|public_key| verified_signature(self.signer_version, &value, sighash, &public_key)),
+                PromiseResult::Failed => {
+                    refund = refund.saturating_add(deposit_per_signature);
+                    Err("Failed to get signature from signer".to_string())
                 }
-                near_sdk::PromiseResult::Failed => {
-                    log!("Failed to get signature from signer");
-                    panic!("Failed to get signature from signer");
+            };
+
+            match signature {
+                Ok(signature) => {
+                    self.record_signature(&request_id, *input_index, SignatureResponse::Secp256k1(signature));
                 }
+                Err(e) => errors.push(format!("input {}: {}", input_index, e)),
             }
         }
+        refund_deposit(&request.requester, refund);
 
-        let tx_hex = self.finalize_btc_tx(
-            prepared_bitcoin_transaction.tx,
-            signatures,
-            signer_public_key
-        );
+        let request = self.expect_request(&request_id);
+        let missing = request.missing_signatures();
+        if !missing.is_empty() {
+            let error = format!("Missing signatures for inputs {:?}: {}", missing, errors.join("; "));
+            self.mark_request_failed(&request_id, error);
+            return None;
+        }
 
-        log!("Finalized BTC transaction hex: {:?}", tx_hex);
-        tx_hex
+        let signatures: Vec<SignResult> = request
+            .signatures
+            .iter()
+            .flatten()
+            .filter_map(|signature| signature.as_secp256k1().cloned())
+            .collect();
+        let tx_hex = build_signed_btc_tx(tx, &signatures, &signer_public_key);
+
+        self.mark_request_signed(&request_id, tx_hex.clone());
+        Some(tx_hex)
     }
 
-    #[private]
     #[payable]
     pub fn sign_evm(
         &mut self,
         tx_request: EvmTransactionRequest,
-    ) -> near_sdk::Promise {
-        log!("Starting sign_evm");
+        path: Option<String>,
+        key_version: Option<u32>,
+    ) -> PromiseOrValue<Option<String>> {
+        self.assert_not_paused(EntryPoint::SignEvm);
+        self.assert_permitted(EntryPoint::SignEvm, Chain::Evm);
+
+        let requester = env::predecessor_account_id();
+        let derivation = KeyDerivation::for_owner(&requester, path, key_version);
+        self.check_key_version(&derivation).unwrap_or_else(|e| env::panic_str(&e));
+        let expected_address = derivation::to_checksum_address(
+            &derivation::evm_address_bytes(&self.derived_public_key(&derivation.path))
+        );
 
-        let prepared_evm_transaction = self.prepare_evm_tx(tx_request);
-        log!("Prepared EVM transaction with hash: {:?}", prepared_evm_transaction.tx_hash);
+        let leftover_deposit = self.leftover_deposit(1);
 
-        self.promise_sign(prepared_evm_transaction.tx_hash, env::attached_deposit())
-            .then(
-                Self::ext(env::current_account_id())
-                    .with_static_gas(SWAP_CALLBACK_GAS.into())
-                    .sign_evm_callback(
-                        prepared_evm_transaction.omni_evm_tx
-                    )
-            )
+        let prepared_evm_transaction = self
+            .prepare_evm_tx(tx_request.clone())
+            .unwrap_or_else(|e| env::panic_str(&e.to_string()));
+
+        let initial_storage_usage = env::storage_usage();
+        let mut approval_amount = None;
+        if self.tracks_value(Chain::Evm, None) {
+            // Only the native value is counted, token transfers encoded in `data` aren't decoded.
+            // Values beyond 128 bits exceed any cap.
+            let value = u128::try_from(prepared_evm_transaction.evm_tx.value).unwrap_or(u128::MAX);
+            let request_id =
+                registry::request_id(&requester, Chain::Evm, &derivation, &[prepared_evm_transaction.tx_hash]);
+            self.record_outflow(&request_id, Chain::Evm, None, value);
+            approval_amount = self.requires_approval(&request_id, Chain::Evm, value).then_some(value);
+        }
+        let request_id = self.register_request(
+            requester.clone(),
+            Chain::Evm,
+            EntryPoint::SignEvm,
+            derivation,
+            PreparedPayload::Evm { tx_request: Box::new(tx_request), expected_address },
+            vec![prepared_evm_transaction.tx_hash],
+        );
+
+        let result = match approval_amount {
+            Some(amount) => {
+                self.queue_approval(&request_id, Chain::Evm, amount, env::attached_deposit());
+                PromiseOrValue::Value(None)
+            }
+            None => self.dispatch_evm_signature(request_id, leftover_deposit).into(),
+        };
+        self.charge_storage(&requester, initial_storage_usage);
+        result
     }
 
     #[private]
     pub fn sign_evm_callback(
         &mut self,
-        omni_evm_tx: omni_transaction::evm::evm_transaction::EVMTransaction,
-        #[callback_result] result: Result<SignResult, PromiseError>
-    ) -> String {
-        match result {
-            Ok(signature) => {
-                log!("Got signature from signer {:?}", signature);
-                let tx_hex = self.finalize_evm_tx(
-                    omni_evm_tx,
-                    signature
-                );
-                log!("Finalized EVM transaction hex: {:?}", tx_hex);
-                tx_hex
+        request_id: RequestId,
+        attempt: u32,
+        deposit_per_signature: NearToken,
+        leftover_deposit: NearToken,
+    ) -> Option<String> {
+        let request = self.expect_request(&request_id).clone();
+        if self.ignore_stale_attempt(&request, attempt, 1, deposit_per_signature, leftover_deposit) {
+            return None;
+        }
+
+        let signature = match self
+            .single_signature_response(&request, deposit_per_signature, leftover_deposit)?
+            .into_secp256k1()
+        {
+            Ok(signature) => signature,
+            Err(e) => {
+                self.mark_request_failed(&request_id, e);
+                return None;
+            }
+        };
+
+        let PreparedPayload::Evm { tx_request, expected_address } = request.payload else {
+            env::panic_str("Not an EVM signature request");
+        };
+
+        // The request was validated when it was submitted
+        let prepared_evm_transaction = self.prepare_evm_tx(*tx_request).expect("Invalid EVM transaction request");
+        match self.finalize_evm_tx(prepared_evm_transaction, signature.clone(), expected_address) {
+            Ok(tx_hex) => {
+                self.record_signature(&request_id, 0, SignatureResponse::Secp256k1(signature));
+                self.mark_request_signed(&request_id, tx_hex.clone());
+                Some(tx_hex)
+            }
+            Err(e) => {
+                self.mark_request_failed(&request_id, e.to_string());
+                None
+            }
+        }
+    }
+
+    /// Sign a Solana or NEAR transaction with an Ed25519 key derived for the caller.
+    ///
+    /// Ed25519 keys are only served by v2 signers, under their own domain.
+    #[payable]
+    pub fn sign_ed25519(
+        &mut self,
+        tx_request: Ed25519TransactionRequest,
+        path: Option<String>,
+        key_version: Option<u32>,
+    ) -> Promise {
+        self.assert_not_paused(EntryPoint::SignEd25519);
+        self.assert_permitted(EntryPoint::SignEd25519, tx_request.chain());
+        if self.signer_version != SignerVersion::V2 {
+            env::panic_str("Ed25519 signatures require a v2 signer");
+        }
+
+        let requester = env::predecessor_account_id();
+        let derivation = KeyDerivation::for_owner(&requester, path, key_version);
+        self.check_key_version(&derivation).unwrap_or_else(|e| env::panic_str(&e));
+        let leftover_deposit = self.leftover_deposit(1);
+
+        let prepared_ed25519_transaction = self.prepare_ed25519_tx(tx_request.clone());
+        let deposit_per_signature = self.signature_deposit;
+        let payload = Payload::Eddsa(hex::encode(&prepared_ed25519_transaction.payload));
+        let sign_promise = self.promise_sign(payload, &derivation, deposit_per_signature);
+        let initial_storage_usage = env::storage_usage();
+        let request_id = self.register_request(
+            requester.clone(),
+            tx_request.chain(),
+            EntryPoint::SignEd25519,
+            derivation,
+            PreparedPayload::Ed25519 { tx_request },
+            vec![env::sha256_array(&prepared_ed25519_transaction.payload)],
+        );
+        self.charge_storage(&requester, initial_storage_usage);
+        let attempt = self.expect_request(&request_id).attempt;
+
+        sign_promise.then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(SWAP_CALLBACK_GAS.saturating_add(VERIFY_GAS_PER_SIGNATURE))
+                .sign_ed25519_callback(request_id, attempt, deposit_per_signature, leftover_deposit)
+        )
+    }
+
+    #[private]
+    pub fn sign_ed25519_callback(
+        &mut self,
+        request_id: RequestId,
+        attempt: u32,
+        deposit_per_signature: NearToken,
+        leftover_deposit: NearToken,
+    ) -> Option<String> {
+        let request = self.expect_request(&request_id).clone();
+        if self.ignore_stale_attempt(&request, attempt, 1, deposit_per_signature, leftover_deposit) {
+            return None;
+        }
+
+        let signature = match self
+            .single_signature_response(&request, deposit_per_signature, leftover_deposit)?
+            .into_ed25519()
+        {
+            Ok(signature) => signature,
+            Err(e) => {
+                self.mark_request_failed(&request_id, e);
+                return None;
+            }
+        };
+
+        let PreparedPayload::Ed25519 { tx_request } = request.payload else {
+            env::panic_str("Not an ed25519 signature request");
+        };
+
+        let prepared_ed25519_transaction = self.prepare_ed25519_tx(tx_request);
+        match self.finalize_ed25519_tx(prepared_ed25519_transaction, signature.clone()) {
+            Ok(tx_hex) => {
+                self.record_signature(&request_id, 0, SignatureResponse::Ed25519 { signature });
+                self.mark_request_signed(&request_id, tx_hex.clone());
+                Some(tx_hex)
             }
             Err(e) => {
-                log!("Failed to get signature from signer: {:?}", e);
-                panic!("Failed to get signature from signer");
+                self.mark_request_failed(&request_id, e.to_string());
+                None
             }
         }
     }
-}
\ No newline at end of file
+
+    /// Fetch the signer's current per-signature deposit, which grows with its load.
+    pub fn sync_signature_deposit(&mut self) -> Promise {
+        ext_signer::ext(self.signer_account.clone())
+            .with_static_gas(SIGNATURE_DEPOSIT_GAS)
+            .experimental_signature_deposit()
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(SIGNATURE_DEPOSIT_CALLBACK_GAS)
+                    .sync_signature_deposit_callback()
+            )
+    }
+
+    #[private]
+    pub fn sync_signature_deposit_callback(&mut self, #[callback_unwrap] deposit: U128) -> NearToken {
+        self.signature_deposit = NearToken::from_yoctonear(deposit.0);
+        BridgeEvent::SignatureDepositChanged { signature_deposit: self.signature_deposit }.emit();
+        self.signature_deposit
+    }
+
+    pub fn get_signature_deposit(&self) -> NearToken {
+        self.signature_deposit
+    }
+
+    pub fn get_signer_version(&self) -> SignerVersion {
+        self.signer_version
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::get_created_receipts;
+    use near_sdk::mock::MockAction;
+    use test_utils::{
+        btc_signature, btc_tx_request, contract_with_relayer, near_tx_request, near_tx_signature, set_context,
+        set_promise_results,
+    };
+
+    #[test]
+    fn test_key_derivation_namespacing() {
+        let owner: AccountId = "alice.testnet".parse().unwrap();
+
+        let default = KeyDerivation::for_owner(&owner, None, None);
+        assert_eq!(default.path, "alice.testnet");
+        assert_eq!(default.key_version, DEFAULT_KEY_VERSION);
+
+        let custom = KeyDerivation::for_owner(&owner, Some("alice.testnet/btc/1".to_string()), Some(1));
+        assert_eq!(custom.path, "alice.testnet/btc/1");
+        assert_eq!(custom.key_version, 1);
+
+        for path in ["", "bob.testnet", "alice.testnet.evil/btc", "alice.testnet/"] {
+            let result = std::panic::catch_unwind(|| {
+                KeyDerivation::for_owner(&owner, Some(path.to_string()), None)
+            });
+            assert!(result.is_err(), "path {:?} should be rejected", path);
+        }
+    }
+
+    #[test]
+    fn test_retry_missing_btc_signature() {
+        let alice: AccountId = "alice.testnet".parse().unwrap();
+        set_context(&alice, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+
+        let mut contract = contract_with_relayer(&alice);
+        contract.sign_btc(btc_tx_request(&alice), None, None);
+        let request_id = contract.get_requests_for_account(alice.clone(), None, None)[0].id.clone();
+
+        // Retrying is only possible once the request has failed
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.retry_btc_signature(request_id.clone(), vec![0])
+        }));
+        assert!(result.is_err());
+
+        set_promise_results(&alice, vec![PromiseResult::Failed]);
+        assert_eq!(contract.sign_btc_callback(request_id.clone(), 1, vec![0], DEFAULT_SIGNATURE_DEPOSIT, NearToken::from_yoctonear(0)), None);
+        let failed = contract.get_request(request_id.clone()).unwrap();
+        assert_eq!(failed.status, RequestStatus::Failed);
+        assert_eq!(failed.missing_signatures(), vec![0]);
+
+        for input_indexes in [vec![], vec![1], vec![0, 0]] {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                contract.retry_btc_signature(request_id.clone(), input_indexes.clone())
+            }));
+            assert!(result.is_err(), "input indexes {:?} should be rejected", input_indexes);
+        }
+
+        // A requester who lost the role allowing SignBtc can't retry either
+        set_context(&alice, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        contract.role_members.get_mut(&Role::Relayer).unwrap().remove(&alice);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.retry_btc_signature(request_id.clone(), vec![0])
+        }));
+        assert!(result.is_err());
+
+        contract.grant_role_internal(Role::Relayer, alice.clone());
+        contract.retry_btc_signature(request_id.clone(), vec![0]);
+        assert_eq!(contract.get_request(request_id.clone()).unwrap().status, RequestStatus::Pending);
+
+        let signature = near_sdk::serde_json::to_vec(&btc_signature(&alice)).unwrap();
+        set_promise_results(&alice, vec![PromiseResult::Successful(signature)]);
+        let tx_hex = contract.sign_btc_callback(request_id.clone(), 2, vec![0], DEFAULT_SIGNATURE_DEPOSIT, NearToken::from_yoctonear(0)).unwrap();
+
+        let signed = contract.get_request(request_id).unwrap();
+        assert_eq!(signed.status, RequestStatus::Signed);
+        assert_eq!(signed.signed_tx, Some(tx_hex));
+        assert!(signed.missing_signatures().is_empty());
+    }
+
+    /// Amounts transferred by the receipts created so far, with their receiver.
+    fn refunds() -> Vec<(AccountId, NearToken)> {
+        get_created_receipts()
+            .into_iter()
+            .flat_map(|receipt| {
+                receipt.actions.into_iter().filter_map(move |action| match action {
+                    MockAction::Transfer { deposit, .. } => Some((receipt.receiver_id.clone(), deposit)),
+                    _ => None,
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_signature_deposit_refunds() {
+        let alice: AccountId = "alice.testnet".parse().unwrap();
+        let signature_deposit = NearToken::from_millinear(50);
+        set_context(&alice, NearToken::from_yoctonear(0), vec![]);
+
+        let mut contract = contract_with_relayer(&alice);
+        contract.signature_deposit = signature_deposit;
+
+        // Underfunded calls are rejected before any signature is requested
+        set_context(&alice, NearToken::from_millinear(49), vec![]);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.sign_btc(btc_tx_request(&alice), None, None)
+        }));
+        assert!(result.is_err());
+        assert!(contract.get_requests_for_account(alice.clone(), None, None).is_empty());
+
+        set_context(&alice, NearToken::from_millinear(80), vec![]);
+        contract.sign_btc(btc_tx_request(&alice), None, None);
+        let request_id = contract.get_requests_for_account(alice.clone(), None, None)[0].id.clone();
+
+        // A failed signature refunds its deposit along with the leftover
+        let leftover = NearToken::from_millinear(30);
+        set_promise_results(&alice, vec![PromiseResult::Failed]);
+        contract.sign_btc_callback(request_id.clone(), 1, vec![0], signature_deposit, leftover);
+        assert_eq!(refunds(), vec![(alice.clone(), NearToken::from_millinear(80))]);
+
+        // A successful signature only refunds the leftover
+        set_context(&alice, signature_deposit, vec![]);
+        contract.retry_btc_signature(request_id.clone(), vec![0]);
+        let signature = near_sdk::serde_json::to_vec(&btc_signature(&alice)).unwrap();
+        set_promise_results(&alice, vec![PromiseResult::Successful(signature)]);
+        contract.sign_btc_callback(request_id, 2, vec![0], signature_deposit, NearToken::from_yoctonear(0));
+        assert!(refunds().is_empty());
+    }
+
+    #[test]
+    fn test_sign_btc_with_v2_signer() {
+        let alice: AccountId = "alice.testnet".parse().unwrap();
+        set_context(&alice, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+
+        let mut contract = contract_with_relayer(&alice);
+        contract.signer_version = SignerVersion::V2;
+        contract.sign_btc(btc_tx_request(&alice), None, None);
+        let request_id = contract.get_requests_for_account(alice.clone(), None, None)[0].id.clone();
+
+        let response = SignatureResponse::Secp256k1(btc_signature(&alice));
+        let signature = near_sdk::serde_json::to_vec(&response).unwrap();
+        set_promise_results(&alice, vec![PromiseResult::Successful(signature)]);
+        let tx_hex = contract.sign_btc_callback(request_id.clone(), 1, vec![0], DEFAULT_SIGNATURE_DEPOSIT, NearToken::from_yoctonear(0));
+
+        assert!(tx_hex.is_some());
+        assert_eq!(contract.get_request(request_id).unwrap().status, RequestStatus::Signed);
+    }
+
+    #[test]
+    fn test_v2_signer_rejects_key_versions() {
+        let alice: AccountId = "alice.testnet".parse().unwrap();
+        set_context(&alice, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+
+        let mut contract = contract_with_relayer(&alice);
+        contract.sign_btc(btc_tx_request(&alice), None, Some(1));
+        let request_id = contract.get_requests_for_account(alice.clone(), None, None)[0].id.clone();
+        set_promise_results(&alice, vec![PromiseResult::Failed]);
+        contract.sign_btc_callback(request_id.clone(), 1, vec![0], DEFAULT_SIGNATURE_DEPOSIT, NearToken::from_yoctonear(0));
+
+        // V2 signers would sign with the default key, whatever version the request was made for
+        contract.signer_version = SignerVersion::V2;
+        set_context(&alice, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        for key_version in [Some(1), Some(2)] {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                contract.sign_btc(btc_tx_request(&alice), None, key_version)
+            }));
+            assert!(result.is_err(), "key version {:?} should be rejected", key_version);
+        }
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.retry_btc_signature(request_id.clone(), vec![0])
+        }));
+        assert!(result.is_err());
+        assert_eq!(contract.get_request(request_id).unwrap().status, RequestStatus::Failed);
+
+        contract.sign_btc(btc_tx_request(&alice), None, Some(DEFAULT_KEY_VERSION));
+        assert_eq!(contract.get_requests_for_account(alice, None, None).len(), 2);
+    }
+
+    #[test]
+    fn test_sign_btc_requires_derived_key() {
+        let alice: AccountId = "alice.testnet".parse().unwrap();
+        let bob: AccountId = "bob.testnet".parse().unwrap();
+        set_context(&alice, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+
+        // A key derived for another account can't be claimed as the signer
+        let mut contract = contract_with_relayer(&alice);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.sign_btc(btc_tx_request(&bob), None, None)
+        }));
+        assert!(result.is_err());
+        assert!(contract.get_requests_for_account(alice.clone(), None, None).is_empty());
+
+        let mut tx_request = btc_tx_request(&alice);
+        tx_request.signer_public_key = tx_request.signer_public_key.to_uppercase();
+        contract.sign_btc(tx_request, None, None);
+        let request = &contract.get_requests_for_account(alice.clone(), None, None)[0];
+        let PreparedPayload::Bitcoin { signer_public_key, .. } = &request.payload else {
+            panic!("Not a bitcoin signature request");
+        };
+        assert_eq!(*signer_public_key, btc_tx_request(&alice).signer_public_key);
+    }
+
+    #[test]
+    fn test_sign_ed25519() {
+        let alice: AccountId = "alice.testnet".parse().unwrap();
+        set_context(&alice, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+
+        let mut contract = contract_with_relayer(&alice);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.sign_ed25519(near_tx_request(), None, None)
+        }));
+        assert!(result.is_err(), "v1 signers can't produce ed25519 signatures");
+
+        contract.signer_version = SignerVersion::V2;
+        contract.sign_ed25519(near_tx_request(), None, None);
+        let request = contract.get_requests_for_account(alice.clone(), None, None)[0].clone();
+        assert_eq!(request.chain, Chain::Near);
+
+        let response = SignatureResponse::Ed25519 { signature: near_tx_signature() };
+        let signature = near_sdk::serde_json::to_vec(&response).unwrap();
+        set_promise_results(&alice, vec![PromiseResult::Successful(signature)]);
+        let tx_hex = contract.sign_ed25519_callback(request.id.clone(), 1, DEFAULT_SIGNATURE_DEPOSIT, NearToken::from_yoctonear(0));
+
+        let signed = contract.get_request(request.id).unwrap();
+        assert_eq!(signed.status, RequestStatus::Signed);
+        assert_eq!(signed.signed_tx, tx_hex);
+        assert!(signed.missing_signatures().is_empty());
+    }
+}
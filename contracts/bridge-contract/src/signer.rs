@@ -1,8 +1,10 @@
 use near_sdk::{
     ext_contract, 
+    json_types::U128,
     AccountId, 
     PromiseOrValue,
     serde::{Deserialize, Serialize},
+    serde_json,
     borsh::{BorshSerialize, BorshDeserialize}
 };
 use schemars::JsonSchema;
@@ -45,6 +47,96 @@ pub struct SignResult {
     pub recovery_id: u8,
 }
 
+/// Request and response format spoken by the signer contract.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum SignerVersion {
+    /// `sign({request: {payload, path, key_version}})`, answered with a bare `SignResult`.
+    V1,
+    /// `sign({request: {payload_v2, path, domain_id}})`, answered with a `SignatureResponse`.
+    V2,
+}
+
+/// Domain of the secp256k1 root key on v2 signers.
+pub const SECP256K1_DOMAIN_ID: u64 = 0;
+/// Domain of the ed25519 root key on v2 signers.
+pub const ED25519_DOMAIN_ID: u64 = 1;
+
+/// Hex-encoded payload, tagged with the signature scheme it should be signed with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Payload {
+    Ecdsa(String),
+    Eddsa(String),
+}
+
+impl Payload {
+    pub fn domain_id(&self) -> u64 {
+        match self {
+            Payload::Ecdsa(_) => SECP256K1_DOMAIN_ID,
+            Payload::Eddsa(_) => ED25519_DOMAIN_ID,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SignRequestV2 {
+    pub payload_v2: Payload,
+    pub path: String,
+    pub domain_id: u64,
+}
+
+impl SignRequestV2 {
+    pub fn new(payload_v2: Payload, path: String) -> Self {
+        Self {
+            domain_id: payload_v2.domain_id(),
+            payload_v2,
+            path,
+        }
+    }
+}
+
+/// Signature returned by a v2 signer, tagged with its scheme.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde", tag = "scheme")]
+pub enum SignatureResponse {
+    Secp256k1(SignResult),
+    Ed25519 { signature: Vec<u8> },
+}
+
+impl SignatureResponse {
+    /// Decode the value returned by `sign` on a signer speaking `version`.
+    pub fn from_json(version: SignerVersion, value: &[u8]) -> Result<Self, String> {
+        match version {
+            SignerVersion::V1 => serde_json::from_slice::<SignResult>(value).map(SignatureResponse::Secp256k1),
+            SignerVersion::V2 => serde_json::from_slice::<SignatureResponse>(value),
+        }
+        .map_err(|e| format!("Failed to deserialize signature: {}", e))
+    }
+
+    pub fn into_secp256k1(self) -> Result<SignResult, String> {
+        match self {
+            SignatureResponse::Secp256k1(signature) => Ok(signature),
+            SignatureResponse::Ed25519 { .. } => Err("Expected a secp256k1 signature, got ed25519".to_string()),
+        }
+    }
+
+    pub fn into_ed25519(self) -> Result<Vec<u8>, String> {
+        match self {
+            SignatureResponse::Ed25519 { signature } => Ok(signature),
+            SignatureResponse::Secp256k1(_) => Err("Expected an ed25519 signature, got secp256k1".to_string()),
+        }
+    }
+
+    pub fn as_secp256k1(&self) -> Option<&SignResult> {
+        match self {
+            SignatureResponse::Secp256k1(signature) => Some(signature),
+            SignatureResponse::Ed25519 { .. } => None,
+        }
+    }
+}
+
 #[ext_contract(ext_signer)]
 pub trait SignerInterface {
     fn sign(&mut self, request: SignRequest) -> PromiseOrValue<SignResult>;
@@ -55,4 +147,48 @@ pub trait SignerInterface {
         predecessor: Option<AccountId>,
     ) -> near_sdk::PublicKey;
     fn latest_key_version(&self) -> u32;
+    fn experimental_signature_deposit(&self) -> U128;
+}
+
+#[ext_contract(ext_signer_v2)]
+pub trait SignerInterfaceV2 {
+    fn sign(&mut self, request: SignRequestV2) -> PromiseOrValue<SignatureResponse>;
+    fn public_key(&self, domain_id: Option<u64>) -> near_sdk::PublicKey;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signer_v2_format() {
+        let request = SignRequestV2::new(Payload::Ecdsa("ab".repeat(32)), "alice.testnet".to_string());
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "payload_v2": { "Ecdsa": "ab".repeat(32) },
+                "path": "alice.testnet",
+                "domain_id": SECP256K1_DOMAIN_ID,
+            })
+        );
+        assert_eq!(Payload::Eddsa("00".to_string()).domain_id(), ED25519_DOMAIN_ID);
+
+        let v1 = r#"{"big_r":{"affine_point":"03E1"},"s":{"scalar":"6899"},"recovery_id":1}"#;
+        let v2 = r#"{"scheme":"Secp256k1","big_r":{"affine_point":"03E1"},"s":{"scalar":"6899"},"recovery_id":1}"#;
+        for (version, value) in [(SignerVersion::V1, v1), (SignerVersion::V2, v2)] {
+            let signature = SignatureResponse::from_json(version, value.as_bytes())
+                .and_then(SignatureResponse::into_secp256k1)
+                .unwrap();
+            assert_eq!(signature.big_r.affine_point, "03E1");
+            assert_eq!(signature.s.scalar, "6899");
+            assert_eq!(signature.recovery_id, 1);
+        }
+
+        // The scheme tag is required by v2 and ignored by v1
+        assert!(SignatureResponse::from_json(SignerVersion::V1, v2.as_bytes()).is_ok());
+        assert!(SignatureResponse::from_json(SignerVersion::V2, v1.as_bytes()).is_err());
+
+        let ed25519 = SignatureResponse::from_json(SignerVersion::V2, br#"{"scheme":"Ed25519","signature":[1,2,3]}"#).unwrap();
+        assert!(ed25519.into_secp256k1().is_err());
+    }
 }
@@ -0,0 +1,264 @@
+use crate::*;
+
+use near_sdk::{env, json_types::U128, log, near, Promise};
+
+/// Bytes of an account's own entry in `storage_accounts`, covered by the registration deposit.
+pub const STORAGE_REGISTRATION_BYTES: u64 = 200;
+
+/// NEP-145 balance of an account.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageBalance {
+    #[schemars(with = "String")]
+    pub total: U128,
+    /// Part of `total` not covering stored records, which can be withdrawn.
+    #[schemars(with = "String")]
+    pub available: U128,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageBalanceBounds {
+    #[schemars(with = "String")]
+    pub min: U128,
+    #[schemars(with = "Option<String>")]
+    pub max: Option<U128>,
+}
+
+/// Storage paid by an account, and the bytes used by the records it created.
+#[derive(Debug, Clone, Default, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+#[borsh(crate = "near_sdk::borsh")]
+pub struct StorageAccount {
+    pub deposit: NearToken,
+    pub used_bytes: u64,
+}
+
+impl StorageAccount {
+    fn required(&self) -> NearToken {
+        env::storage_byte_cost().saturating_mul((STORAGE_REGISTRATION_BYTES + self.used_bytes) as u128)
+    }
+
+    fn available(&self) -> NearToken {
+        self.deposit.saturating_sub(self.required())
+    }
+
+    fn balance(&self) -> StorageBalance {
+        StorageBalance {
+            total: U128(self.deposit.as_yoctonear()),
+            available: U128(self.available().as_yoctonear()),
+        }
+    }
+}
+
+fn storage_balance_min() -> NearToken {
+    env::storage_byte_cost().saturating_mul(STORAGE_REGISTRATION_BYTES as u128)
+}
+
+impl Contract {
+    /// Write cached collection entries, so `env::storage_usage` accounts for them.
+    fn flush_records(&mut self, account_id: &AccountId) {
+        if let Some(request_ids) = self.account_requests.get_mut(account_id) {
+            request_ids.flush();
+        }
+        self.account_requests.flush();
+        self.requests.flush();
+        self.lps.flush();
+        self.lp_by_btc_public_key.flush();
+        self.pool_balances.flush();
+        self.pool_debits.flush();
+        self.treasury_balances.flush();
+        self.pending_fees.flush();
+        self.outflows.flush();
+        self.approvals.flush();
+        self.approval_queue.flush();
+    }
+
+    /// Add the bytes stored or freed since `initial_storage_usage` to `account_id`.
+    ///
+    /// Callbacks can't refuse the records they complete, so this never fails; the next
+    /// `charge_storage` of the account requires its deposit to cover them.
+    pub(crate) fn record_storage_usage(&mut self, account_id: &AccountId, initial_storage_usage: u64) {
+        self.flush_records(account_id);
+        let Some(account) = self.storage_accounts.get_mut(account_id) else {
+            return;
+        };
+
+        let current_storage_usage = env::storage_usage();
+        account.used_bytes = if current_storage_usage >= initial_storage_usage {
+            account.used_bytes.saturating_add(current_storage_usage - initial_storage_usage)
+        } else {
+            account.used_bytes.saturating_sub(initial_storage_usage - current_storage_usage)
+        };
+    }
+
+    /// Charge the bytes stored since `initial_storage_usage` to `account_id`, which must have
+    /// deposited enough to cover all of its records.
+    pub(crate) fn charge_storage(&mut self, account_id: &AccountId, initial_storage_usage: u64) {
+        if !self.storage_accounts.contains_key(account_id) {
+            env::panic_str(&format!("{} is not registered for storage", account_id));
+        }
+        self.record_storage_usage(account_id, initial_storage_usage);
+
+        let account = &self.storage_accounts[account_id];
+        if account.deposit < account.required() {
+            env::panic_str(&format!(
+                "Storage deposit of {} is {} but its records require {}",
+                account_id,
+                account.deposit,
+                account.required()
+            ));
+        }
+    }
+}
+
+#[near]
+impl Contract {
+    /// Deposit storage for `account_id`, the caller by default. With `registration_only`,
+    /// only the minimum balance is kept for new accounts and the rest is refunded.
+    #[payable]
+    pub fn storage_deposit(&mut self, account_id: Option<AccountId>, registration_only: Option<bool>) -> StorageBalance {
+        let amount = env::attached_deposit();
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let min = storage_balance_min();
+
+        let (deposit, refund) = match self.storage_accounts.get(&account_id) {
+            Some(_) if registration_only == Some(true) => (NearToken::from_yoctonear(0), amount),
+            Some(_) => (amount, NearToken::from_yoctonear(0)),
+            None if amount < min => env::panic_str(&format!("The minimum storage balance is {}", min)),
+            None if registration_only == Some(true) => (min, amount.saturating_sub(min)),
+            None => (amount, NearToken::from_yoctonear(0)),
+        };
+
+        let account = self.storage_accounts.entry(account_id).or_default();
+        account.deposit = account.deposit.saturating_add(deposit);
+        let balance = account.balance();
+
+        if !refund.is_zero() {
+            Promise::new(env::predecessor_account_id()).transfer(refund);
+        }
+        balance
+    }
+
+    /// Withdraw `amount` of the caller's available balance, all of it by default.
+    #[payable]
+    pub fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+
+        let account_id = env::predecessor_account_id();
+        let account = self
+            .storage_accounts
+            .get_mut(&account_id)
+            .unwrap_or_else(|| env::panic_str(&format!("{} is not registered for storage", account_id)));
+
+        let available = account.available();
+        let amount = amount.map_or(available, |amount| NearToken::from_yoctonear(amount.0));
+        if amount > available {
+            env::panic_str(&format!("Only {} is available to withdraw", available));
+        }
+        account.deposit = account.deposit.saturating_sub(amount);
+        let balance = account.balance();
+
+        if !amount.is_zero() {
+            Promise::new(account_id).transfer(amount);
+        }
+        balance
+    }
+
+    /// Close the caller's storage account and refund its deposit. Accounts still owning records
+    /// can't unregister, as records are kept for auditing and `force` is not supported.
+    #[payable]
+    pub fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+        if force == Some(true) {
+            env::panic_str("Records can't be removed, force is not supported");
+        }
+
+        let account_id = env::predecessor_account_id();
+        let Some(account) = self.storage_accounts.get(&account_id) else {
+            log!("{} is not registered for storage", account_id);
+            return false;
+        };
+        if account.used_bytes > 0 {
+            env::panic_str("Can't unregister while records are stored");
+        }
+
+        let deposit = account.deposit;
+        self.storage_accounts.remove(&account_id);
+        Promise::new(account_id).transfer(deposit.saturating_add(NearToken::from_yoctonear(1)));
+        true
+    }
+
+    pub fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: U128(storage_balance_min().as_yoctonear()),
+            max: None,
+        }
+    }
+
+    pub fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.storage_accounts.get(&account_id).map(StorageAccount::balance)
+    }
+}
+
+fn assert_one_yocto() {
+    if env::attached_deposit() != NearToken::from_yoctonear(1) {
+        env::panic_str("Requires attached deposit of exactly 1 yoctoNEAR");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sign::DEFAULT_SIGNATURE_DEPOSIT;
+    use test_utils::{btc_tx_request, contract_with_relayer, set_context};
+
+    #[test]
+    fn test_storage_deposit_and_withdraw() {
+        let alice: AccountId = "alice.testnet".parse().unwrap();
+        let min = storage_balance_min();
+        set_context(&alice, min.saturating_mul(3), vec![]);
+
+        let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+        assert_eq!(contract.storage_balance_of(alice.clone()), None);
+        assert_eq!(contract.storage_balance_bounds().min, U128(min.as_yoctonear()));
+
+        let balance = contract.storage_deposit(None, Some(true));
+        assert_eq!(balance, StorageBalance { total: U128(min.as_yoctonear()), available: U128(0) });
+
+        let balance = contract.storage_deposit(None, None);
+        assert_eq!(balance.available, U128(min.saturating_mul(3).as_yoctonear()));
+
+        set_context(&alice, NearToken::from_yoctonear(1), vec![]);
+        let balance = contract.storage_withdraw(Some(U128(min.as_yoctonear())));
+        assert_eq!(balance.available, U128(min.saturating_mul(2).as_yoctonear()));
+        assert!(contract.storage_unregister(None));
+        assert_eq!(contract.storage_balance_of(alice), None);
+    }
+
+    #[test]
+    fn test_requests_are_charged_to_requester() {
+        let alice: AccountId = "alice.testnet".parse().unwrap();
+        set_context(&alice, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+
+        let mut contract = contract_with_relayer(&alice);
+        contract.sign_btc(btc_tx_request(&alice), None, None);
+
+        let account = &contract.storage_accounts[&alice];
+        assert!(account.used_bytes > 0);
+        assert_eq!(
+            contract.storage_balance_of(alice.clone()).unwrap().available,
+            U128(account.deposit.saturating_sub(account.required()).as_yoctonear())
+        );
+
+        // Records can't be left unpaid
+        set_context(&alice, NearToken::from_yoctonear(1), vec![]);
+        contract.storage_withdraw(None);
+        set_context(&alice, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        let mut tx_request = btc_tx_request(&alice);
+        tx_request.outputs[0].value += 1;
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.sign_btc(tx_request, None, None)
+        }));
+        assert!(result.is_err());
+    }
+}
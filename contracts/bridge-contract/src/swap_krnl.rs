@@ -1,41 +1,281 @@
 use crate::*;
 
-use btc::{BtcInput, BtcOutput, BitcoinTransactionRequest};
-use near_sdk::{log, Promise};
+use admin::EntryPoint;
+use btc::{BtcInput, BtcOutput, BitcoinTransactionRequest, PreparedBitcoinTransaction};
+use derivation::compressed_public_key;
+use events::BridgeEvent;
+use near_sdk::{env, json_types::U128, PromiseOrValue};
+use pool::{btc_outflow, PoolPayout};
+use sign::{refund_deposit, KeyDerivation};
 
-#[near]
-impl Contract {
+/// Payout a kernel response resolves to, once it has been checked against the registry and the pool.
+struct SwapPayout {
+    lp_account_id: AccountId,
+    derivation: KeyDerivation,
+    prepared_bitcoin_transaction: PreparedBitcoinTransaction,
+    signer_public_key: String,
+    request_id: RequestId,
+    amount: u128,
+    fee: u128,
+}
 
-    #[payable]
-    pub fn swap_btc_krnl(&mut self, auth: String, sender: String, recipient: String, kernel_response: String) -> Promise {
+/// UTXO values come from the kernel as decimal strings of up to 256 bits.
+fn parse_satoshis(value: &str) -> Result<u64, String> {
+    value.parse::<u64>().map_err(|_| format!("Invalid UTXO value {}", value))
+}
+
+impl Contract {
+    fn swap_payout(
+        &mut self,
+        auth: String,
+        sender: String,
+        recipient: String,
+        kernel_response: String,
+        path: Option<String>,
+        key_version: Option<u32>,
+    ) -> Result<SwapPayout, String> {
         let is_authorized = self.is_krnl_authorized(auth, sender, recipient, kernel_response.clone());
         let kernel_response = self.decode_krnl_response(kernel_response);
 
         if !is_authorized {
-            panic!("Unauthorized");
+            return Err("Unauthorized".to_string());
         }
 
-        let input_utxos = kernel_response.liquidity.input_utxos.iter().map(|utxo| BtcInput {
+        let input_utxos = kernel_response.liquidity.input_utxos.iter().map(|utxo| Ok(BtcInput {
             txid: utxo.txid.clone(),
             vout: utxo.vout as u32,
-            value: utxo.value.parse::<u64>().unwrap(),
+            value: parse_satoshis(&utxo.value)?,
             script_pubkey: utxo.script_pubkey.clone()
-        }).collect();
+        })).collect::<Result<_, String>>()?;
 
-        let output_utxos = kernel_response.liquidity.output_utxos.iter().map(|utxo| BtcOutput {
-            value: utxo.value.parse::<u64>().unwrap(),
+        let output_utxos = kernel_response.liquidity.output_utxos.iter().map(|utxo| Ok(BtcOutput {
+            value: parse_satoshis(&utxo.value)?,
             script_pubkey: utxo.script_pubkey.clone()
-        }).collect();
+        })).collect::<Result<_, String>>()?;
         let sender_public_key = kernel_response.lp_pubkey;
+        // The LP's liquidity is spent, so the key is derived under the LP's account
+        let lp = self.active_lp_by_btc_public_key(&sender_public_key)?;
+        let derivation = KeyDerivation::for_owner(&lp.account_id, path, key_version);
+        self.check_key_version(&derivation)?;
+        let lp_account_id = lp.account_id.clone();
+        let lp_script_pubkey = lp.btc_script_pubkey.clone();
+        let lp_public_key = lp.btc_public_key.clone();
+        if hex::encode(compressed_public_key(&self.derived_public_key(&derivation.path))) != lp_public_key {
+            return Err(format!("{} doesn't derive the LP's BTC public key", derivation.path));
+        }
 
-        log!("Input UTXOs: {:?}", input_utxos);
-        log!("Output UTXOs: {:?}", output_utxos);
-        log!("Sender Public Key: {:?}", sender_public_key);
-
-        self.sign_btc(BitcoinTransactionRequest {
+        let tx_request = BitcoinTransactionRequest {
             inputs: input_utxos,
             outputs: output_utxos,
-            signer_public_key: sender_public_key
+            signer_public_key: lp_public_key
+        };
+        // Checked against the pool's recorded liquidity, not the kernel's `sufficient` flag
+        let amount = btc_outflow(&tx_request, &lp_script_pubkey)?;
+        let fee = self.check_btc_fee(&tx_request, &lp_script_pubkey)?;
+
+        let prepared_bitcoin_transaction = self.prepare_btc_tx(tx_request.clone());
+        let request_id = registry::request_id(&env::predecessor_account_id(), Chain::Bitcoin, &derivation, &prepared_bitcoin_transaction.sighashes);
+        self.check_pool_debit(&lp_account_id, Chain::Bitcoin, &request_id, amount)?;
+        self.check_outflow(&request_id, Chain::Bitcoin, Some(&lp_account_id), amount)?;
+
+        Ok(SwapPayout {
+            lp_account_id,
+            derivation,
+            prepared_bitcoin_transaction,
+            signer_public_key: tx_request.signer_public_key,
+            request_id,
+            amount,
+            fee,
         })
     }
 }
+
+#[near]
+impl Contract {
+    /// Sign the BTC payout of a KRNL-authorized swap from the LP's pool.
+    ///
+    /// A swap the kernel didn't authorize, or that the LP can't pay, emits `swap_rejected`,
+    /// refunds the attached deposit and resolves to `None` instead of failing.
+    #[payable]
+    pub fn swap_btc_krnl(
+        &mut self,
+        auth: String,
+        sender: String,
+        recipient: String,
+        kernel_response: String,
+        path: Option<String>,
+        key_version: Option<u32>,
+    ) -> PromiseOrValue<Option<String>> {
+        self.assert_not_paused(EntryPoint::SwapBtcKrnl);
+        self.assert_permitted(EntryPoint::SwapBtcKrnl, Chain::Bitcoin);
+
+        let requester = env::predecessor_account_id();
+        let payout = match self.swap_payout(auth, sender, recipient, kernel_response, path, key_version) {
+            Ok(payout) => payout,
+            Err(reason) => {
+                BridgeEvent::SwapRejected { requester: requester.clone(), reason }.emit();
+                refund_deposit(&requester, env::attached_deposit());
+                return PromiseOrValue::Value(None);
+            }
+        };
+
+        BridgeEvent::SwapAuthorized {
+            requester: requester.clone(),
+            lp_account_id: payout.lp_account_id.clone(),
+            request_id: payout.request_id.clone(),
+            amount: U128(payout.amount),
+        }
+        .emit();
+
+        let initial_storage_usage = env::storage_usage();
+        self.record_outflow(&payout.request_id, Chain::Bitcoin, Some(&payout.lp_account_id), payout.amount);
+        let approval_amount = self
+            .requires_approval(&payout.request_id, Chain::Bitcoin, payout.amount)
+            .then_some(payout.amount);
+        let result = self.internal_sign_btc(
+            requester.clone(),
+            payout.derivation,
+            payout.prepared_bitcoin_transaction,
+            payout.signer_public_key,
+            Some(PoolPayout { account_id: payout.lp_account_id, amount: payout.amount, fee: payout.fee }),
+            approval_amount,
+        );
+        self.charge_storage(&requester, initial_storage_usage);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use limits::OutflowCap;
+    use lp::RiskTier;
+    use near_sdk::{test_utils::get_logs, PromiseResult};
+    use sign::DEFAULT_SIGNATURE_DEPOSIT;
+    use registry::RequestStatus;
+    use test_utils::{
+        btc_deposit_proof, krnl_swap, prove_deposit, register_storage, set_context, set_promise_results,
+        sync_mpc_public_key_deriving, KrnlSwap, KRNL_LP_PUBLIC_KEY,
+    };
+
+    /// Total of the swap's only input: its change pays the LP's key directly, so it isn't counted back.
+    const SWAP_OUTFLOW: u128 = 291976;
+
+    fn swap(contract: &mut Contract) -> PromiseOrValue<Option<String>> {
+        let KrnlSwap { auth, sender, recipient, kernel_response } = krnl_swap();
+        contract.swap_btc_krnl(auth, sender, recipient, kernel_response, None, None)
+    }
+
+    fn deposit(contract: &mut Contract, lp: &AccountId, amount: u128, salt: u8) {
+        let owner = contract.owner_id.clone();
+        let proof = btc_deposit_proof(&contract.expect_lp(lp).btc_script_pubkey, amount as u64, salt);
+        prove_deposit(contract, &owner, lp, proof, true).unwrap();
+    }
+
+    #[test]
+    fn test_parse_satoshis() {
+        assert_eq!(parse_satoshis("291976"), Ok(291976));
+        // Kernel values are uint256, larger ones reject the swap instead of trapping
+        assert_eq!(parse_satoshis("18446744073709551616"), Err("Invalid UTXO value 18446744073709551616".to_string()));
+        assert!(parse_satoshis("0.1").is_err());
+    }
+
+    #[test]
+    fn test_swap_against_pool() {
+        let owner: AccountId = "alice.near".parse().unwrap();
+        let lp: AccountId = "lp.testnet".parse().unwrap();
+        set_context(&owner, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+
+        let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+        register_storage(&mut contract, &owner);
+        register_storage(&mut contract, &lp);
+        sync_mpc_public_key_deriving(&mut contract, &lp, KRNL_LP_PUBLIC_KEY);
+        assert!(matches!(swap(&mut contract), PromiseOrValue::Value(None)));
+        assert!(get_logs().iter().any(|log| log.contains("\"event\":\"swap_rejected\"") && log.contains("registered LP")));
+
+        contract.register_lp(lp.clone(), KRNL_LP_PUBLIC_KEY.to_string(), vec![], RiskTier::Low);
+        contract.set_btc_light_client("btc-client.testnet".parse().unwrap(), 6);
+        deposit(&mut contract, &lp, SWAP_OUTFLOW - 1, 1);
+        set_context(&owner, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        assert!(matches!(swap(&mut contract), PromiseOrValue::Value(None)));
+        assert!(get_logs().iter().any(|log| log.contains("\"event\":\"swap_rejected\"") && log.contains("exceeds")));
+
+        deposit(&mut contract, &lp, 1, 2);
+        let cap = OutflowCap { amount: U128(SWAP_OUTFLOW - 1), window_ns: 60 * 60 * 1_000_000_000 };
+        contract.set_outflow_cap(Chain::Bitcoin, Some(lp.clone()), Some(cap));
+        set_context(&owner, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        assert!(matches!(swap(&mut contract), PromiseOrValue::Value(None)));
+        assert!(get_logs().iter().any(|log| log.contains("\"event\":\"swap_rejected\"") && log.contains("outflow cap")));
+
+        contract.set_outflow_cap(Chain::Bitcoin, Some(lp.clone()), None);
+        // The payout is signed by the LP's registered key, which other paths don't derive
+        set_context(&owner, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        let KrnlSwap { auth, sender, recipient, kernel_response } = krnl_swap();
+        let result = contract.swap_btc_krnl(auth, sender, recipient, kernel_response, Some("lp.testnet/1".to_string()), None);
+        assert!(matches!(result, PromiseOrValue::Value(None)));
+        assert!(get_logs().iter().any(|log| log.contains("\"event\":\"swap_rejected\"") && log.contains("doesn't derive")));
+
+        set_context(&owner, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        assert!(matches!(swap(&mut contract), PromiseOrValue::Promise(_)));
+        let logs = get_logs();
+        assert!(logs.iter().any(|log| log.contains("\"event\":\"swap_authorized\"")));
+        assert!(logs.iter().any(|log| log.contains("\"event\":\"sign_requested\"")));
+        assert_eq!(contract.get_pool_balance(lp, Chain::Bitcoin), U128(0));
+
+        // The payout is retried under the swap's pause flag, not the one of sign_btc
+        let request_id = contract.get_requests_for_account(owner.clone(), None, None)[0].id.clone();
+        set_promise_results(&owner, vec![PromiseResult::Failed]);
+        contract.sign_btc_callback(request_id.clone(), 1, vec![0], DEFAULT_SIGNATURE_DEPOSIT, NearToken::from_yoctonear(0));
+        set_context(&owner, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        contract.pause(EntryPoint::SwapBtcKrnl);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.retry_btc_signature(request_id.clone(), vec![0])
+        }));
+        assert!(result.is_err());
+
+        contract.unpause(EntryPoint::SwapBtcKrnl);
+        contract.pause(EntryPoint::SignBtc);
+        contract.retry_btc_signature(request_id.clone(), vec![0]);
+        assert_eq!(contract.get_request(request_id).unwrap().status, RequestStatus::Pending);
+    }
+
+    #[test]
+    fn test_release_failed_payout() {
+        let owner: AccountId = "alice.near".parse().unwrap();
+        let lp: AccountId = "lp.testnet".parse().unwrap();
+        set_context(&owner, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+
+        let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+        register_storage(&mut contract, &owner);
+        register_storage(&mut contract, &lp);
+        sync_mpc_public_key_deriving(&mut contract, &lp, KRNL_LP_PUBLIC_KEY);
+        contract.register_lp(lp.clone(), KRNL_LP_PUBLIC_KEY.to_string(), vec![], RiskTier::Low);
+        contract.set_btc_light_client("btc-client.testnet".parse().unwrap(), 6);
+        deposit(&mut contract, &lp, SWAP_OUTFLOW, 1);
+        set_context(&owner, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        assert!(matches!(swap(&mut contract), PromiseOrValue::Promise(_)));
+        let request_id = contract.get_requests_for_account(owner.clone(), None, None)[0].id.clone();
+
+        // Liquidity stays held while the payout is pending
+        set_context(&owner, NearToken::from_yoctonear(0), vec![]);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.release_pool_debit(request_id.clone())
+        }));
+        assert!(result.is_err());
+
+        // A failed payout doesn't expire, its liquidity is released directly
+        set_promise_results(&owner, vec![PromiseResult::Failed]);
+        contract.sign_btc_callback(request_id.clone(), 1, vec![0], DEFAULT_SIGNATURE_DEPOSIT, NearToken::from_yoctonear(0));
+        assert_eq!(contract.get_request(request_id.clone()).unwrap().status, RequestStatus::Failed);
+        set_context(&owner, NearToken::from_yoctonear(0), vec![]);
+        contract.release_pool_debit(request_id.clone());
+        assert_eq!(contract.get_pool_balance(lp, Chain::Bitcoin), U128(SWAP_OUTFLOW));
+        assert!(contract.get_pool_debit(request_id.clone()).unwrap().released);
+
+        set_context(&owner, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.retry_btc_signature(request_id.clone(), vec![0])
+        }));
+        assert!(result.is_err());
+    }
+}
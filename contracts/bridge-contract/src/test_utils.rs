@@ -0,0 +1,200 @@
+use crate::*;
+
+use btc::{BitcoinTransactionRequest, BtcInput, BtcOutput};
+use derivation::{compressed_public_key, derive_epsilon};
+use ed25519::Ed25519TransactionRequest;
+use k256::{ecdsa::SigningKey, elliptic_curve::sec1::ToEncodedPoint, AffinePoint, ProjectivePoint, Scalar};
+use near_sdk::{
+    env, json_types::U128, test_utils::VMContextBuilder, testing_env, CurveType, NearToken, PromiseResult, PublicKey,
+    RuntimeFeesConfig,
+};
+use pool::BtcDepositProof;
+use sign::KeyDerivation;
+use signer::{SerializableAffinePoint, SerializableScalar, SignResult};
+use verify::parse_public_key_hex;
+
+/// Sighash of the only input of `btc_tx_request`, which doesn't depend on the signing key.
+const BTC_TX_SIGHASH: [u8; 32] = [
+    224, 73, 126, 48, 217, 94, 79, 58, 71, 74, 219, 119, 243, 197, 183, 197, 103, 2, 227, 119, 154, 47, 20, 175, 240,
+    168, 89, 60, 152, 92, 190, 186,
+];
+
+/// Secret of the key the signer of `sync_test_mpc_public_key` derives for `owner`'s default path.
+fn derived_test_secret(owner: &AccountId) -> Scalar {
+    let path = KeyDerivation::for_owner(owner, None, None).path;
+    Scalar::from(42u64) + derive_epsilon(&env::current_account_id(), &path)
+}
+
+/// Single-input P2WPKH spend by the key derived for `owner`, signed by `btc_signature`.
+pub fn btc_tx_request(owner: &AccountId) -> BitcoinTransactionRequest {
+    let public_key = (ProjectivePoint::GENERATOR * derived_test_secret(owner)).to_affine();
+    BitcoinTransactionRequest {
+        inputs: vec![BtcInput {
+            txid: "b9d3e0a416120f99f178bb3d95a87173bdb51d5e38da04db0179b3124fbc5370".to_string(),
+            vout: 1,
+            value: 430506,
+            script_pubkey: "00140d7d0223d302b4e8ef37050b5200b1c3306ae7ab".to_string(),
+        }],
+        outputs: vec![
+            BtcOutput {
+                value: 1200,
+                script_pubkey: "0014d3ae5a5de66aa44e7d5723b74e590340b3212f46".to_string(),
+            },
+            BtcOutput {
+                value: 428854,
+                script_pubkey: "00140d7d0223d302b4e8ef37050b5200b1c3306ae7ab".to_string(),
+            },
+        ],
+        signer_public_key: hex::encode(compressed_public_key(&public_key)),
+    }
+}
+
+/// Valid signature for the only input of `btc_tx_request(owner)`.
+pub fn btc_signature(owner: &AccountId) -> SignResult {
+    sign_hash(&derived_test_secret(owner).to_bytes().into(), &BTC_TX_SIGHASH)
+}
+
+/// Recoverable signature of `hash` by `secret`, in the format of the signer's response.
+pub fn sign_hash(secret: &[u8; 32], hash: &[u8; 32]) -> SignResult {
+    let (signature, recovery_id) = SigningKey::from_bytes(secret.into())
+        .unwrap()
+        .sign_prehash_recoverable(hash)
+        .unwrap();
+    let prefix = if recovery_id.is_y_odd() { "03" } else { "02" };
+    SignResult {
+        big_r: SerializableAffinePoint { affine_point: format!("{}{}", prefix, hex::encode(signature.r().to_bytes())) },
+        s: SerializableScalar { scalar: hex::encode(signature.s().to_bytes()) },
+        recovery_id: recovery_id.to_byte(),
+    }
+}
+
+/// 1 NEAR transfer from `alice.testnet` to `bob.testnet`, signed by `near_tx_signature`.
+pub fn near_tx_request() -> Ed25519TransactionRequest {
+    Ed25519TransactionRequest::Near {
+        transaction: "0d000000616c6963652e746573746e65740003a107bff3ce10be1d70dd18e74bc09967e4d6309ba50d5f1ddc8664125531b801000000000000000b000000626f622e746573746e657409090909090909090909090909090909090909090909090909090909090909090100000003000000a1edccce1bc2d3000000000000".to_string(),
+    }
+}
+
+/// Valid ed25519 signature for `near_tx_request`.
+pub fn near_tx_signature() -> Vec<u8> {
+    hex::decode("6a9f9d52452a867217ebe68e707514ec54b1c0bbca4b88e786346993eb3711f7cd8a77198415f78a8a303db347247f9f1e23b660080256e20b38123da47ab90e").unwrap()
+}
+
+/// Fresh contract where `relayer` may use every signing entry point and has paid for storage.
+pub fn contract_with_relayer(relayer: &AccountId) -> Contract {
+    let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+    sync_test_mpc_public_key(&mut contract);
+    contract.grant_role_internal(Role::Relayer, relayer.clone());
+    register_storage(&mut contract, relayer);
+    contract
+}
+
+/// Give `account_id` enough storage deposit for any test.
+pub fn register_storage(contract: &mut Contract, account_id: &AccountId) {
+    contract.storage_accounts.insert(account_id.clone(), StorageAccount {
+        deposit: NearToken::from_near(1),
+        used_bytes: 0,
+    });
+}
+
+fn sync_mpc_root(contract: &mut Contract, root: AffinePoint) {
+    let mut near_key = vec![CurveType::SECP256K1 as u8];
+    near_key.extend_from_slice(&root.to_encoded_point(false).as_bytes()[1..]);
+    contract.sync_mpc_public_key_callback(PublicKey::try_from(near_key).unwrap());
+}
+
+/// Sync the root key of a signer whose secret is 42, so addresses can be derived.
+pub fn sync_test_mpc_public_key(contract: &mut Contract) {
+    sync_mpc_root(contract, (ProjectivePoint::GENERATOR * Scalar::from(42u64)).to_affine());
+}
+
+/// Sync a root key from which `btc_public_key` is the key derived for `owner`, for fixtures
+/// signed by a given LP key.
+pub fn sync_mpc_public_key_deriving(contract: &mut Contract, owner: &AccountId, btc_public_key: &str) {
+    let path = KeyDerivation::for_owner(owner, None, None).path;
+    let epsilon = derive_epsilon(&env::current_account_id(), &path);
+    let public_key = parse_public_key_hex(btc_public_key).unwrap();
+    sync_mpc_root(contract, (ProjectivePoint::from(public_key) - ProjectivePoint::GENERATOR * epsilon).to_affine());
+}
+
+/// Legacy transaction paying `amount` to `script_pubkey`, spending an input that `salt` makes unique.
+pub fn btc_deposit_proof(script_pubkey: &str, amount: u64, salt: u8) -> BtcDepositProof {
+    let script = hex::decode(script_pubkey).unwrap();
+    let tx = [
+        &1u32.to_le_bytes()[..],
+        &[1],
+        &[salt; 32],
+        &0u32.to_le_bytes(),
+        &[0],
+        &u32::MAX.to_le_bytes(),
+        &[1],
+        &amount.to_le_bytes(),
+        &[script.len() as u8],
+        &script,
+        &0u32.to_le_bytes(),
+    ]
+    .concat();
+
+    BtcDepositProof {
+        tx: hex::encode(tx),
+        output_index: 0,
+        block_hash: "00000000000000000002a7c4c1e48d76c5a37902165a270156b7a8d72728a054".to_string(),
+        tx_index: 1,
+        merkle_proof: vec!["8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87".to_string()],
+    }
+}
+
+/// Record `proof` as `predecessor`, with the light client answering `verified`.
+pub fn prove_deposit(
+    contract: &mut Contract,
+    predecessor: &AccountId,
+    account_id: &AccountId,
+    proof: BtcDepositProof,
+    verified: bool,
+) -> Option<U128> {
+    set_context(predecessor, NearToken::from_yoctonear(0), vec![]);
+    let _ = contract.record_deposit(account_id.clone(), proof.clone());
+
+    let tx = btc::parse_btc_tx(&hex::decode(&proof.tx).unwrap()).unwrap();
+    let amount = U128(tx.outputs[proof.output_index as usize].value as u128);
+    set_promise_results(predecessor, vec![PromiseResult::Successful(verified.to_string().into_bytes())]);
+    contract.record_deposit_callback(account_id.clone(), tx.txid, proof.output_index, amount)
+}
+
+/// Reset the context for `predecessor`, with the given results for the callback to read.
+pub fn set_promise_results(predecessor: &AccountId, promise_results: Vec<PromiseResult>) {
+    set_context(predecessor, NearToken::from_yoctonear(0), promise_results);
+}
+
+/// Reset the context for `predecessor` calling with `attached_deposit`.
+pub fn set_context(predecessor: &AccountId, attached_deposit: NearToken, promise_results: Vec<PromiseResult>) {
+    testing_env!(
+        VMContextBuilder::new()
+            .predecessor_account_id(predecessor.clone())
+            .attached_deposit(attached_deposit)
+            .build(),
+        near_sdk::test_vm_config(),
+        RuntimeFeesConfig::test(),
+        Default::default(),
+        promise_results
+    );
+}
+
+/// Arguments of a swap authorized by the kernel, paying out of the LP with key `KRNL_LP_PUBLIC_KEY`.
+pub struct KrnlSwap {
+    pub auth: String,
+    pub sender: String,
+    pub recipient: String,
+    pub kernel_response: String,
+}
+
+pub const KRNL_LP_PUBLIC_KEY: &str = "026cc974b323d462e620538bc515c3b3a93a26fa32a13e0d51137dd64c5b6e1e7d";
+
+pub fn krnl_swap() -> KrnlSwap {
+    KrnlSwap {
+        auth: "0000000000000000000000000000000000000000000000000000000000000040fabf7defe52f452b51b27254203181349ff854addae2768c104fc3840937379f000000000000000000000000000000000000000000000000000000000000004164245338014c81a1b70a4d84be8054ffdb7abdce4a568bdad6ae31f62d8809b0374d18a4765257a933af12c3d9a4526c7a5871979c5c51496cad5b469ebe97950100000000000000000000000000000000000000000000000000000000000000".to_string(),
+        sender: "889E6a9d863373A7A735AB71Cd481e63ef8d64A4".to_string(),
+        recipient: "tb1qh4tnh45v4ulprt0ruyct6p33ej3mh28jsd656k".to_string(),
+        kernel_response: "000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000a000000000000000000000000000000000000000000000000000000000000000e000000000000000000000000000000000000000000000000000000000000002e000000000000000000000000000000000000000000000000000000000000003200000000000000000000000000000000000000000000000000000000000000e40000000000000000000000000000000000000000000000000000000000000000a302e30303033363230340000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000040000000000000000000000000889e6a9d863373a7a735ab71cd481e63ef8d64a4000000000000000000000000889e6a9d863373a7a735ab71cd481e63ef8d64a400000000000000000000000000000000000000000000000000000000000000019f450d2b17ba679992e662a246056050c312893019a6d7ccc48ac6d11eee1fb700000000000000000000000000000000000000000000000000000000006f765cfe7d12888b2bffa36869689673b02b65911f1a1e30035f6050ed445ca21b6334000000000000000000000000000000000000000000000000000000000000006e00000000000000000000000000000000000000000000000000000000000052080000000000000000000000000000000000000000000000000000000000d8a19c000000000000000000000000000000000000000000000000000000400d539aac0000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000001a00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000a302e30313031303230300000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000001e00000000000000000000000000000000000000000000000000000000000000220000000000000000000000000000000000000000000000000000000000000026000000000000000000000000000000000000000000000000000000000000002a000000000000000000000000000000000000000000000000000000000000002e000000000000000000000000000000000000000000000000000000000000003200000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000036000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000056000000000000000000000000000000000000000000000000000000000000006e0000000000000000000000000000000000000000000000000000000000000000a302e303032393139373600000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000a302e303032393139373600000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000a302e303030333635303800000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000a302e303032353534363800000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000a302e303030303033303800000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000a302e303030303033303800000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000200000000000000000000000000000000000000000000000000000000000000080000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000e0000000000000000000000000000000000000000000000000000000000004748800000000000000000000000000000000000000000000000000000000000000403330373735616431663663633363383431626536643264313531396361336662393934366464656632636261353561363332623663306536633335633535323800000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000080000000000000000000000000000000000000000000000000000000000000e53e0000000000000000000000000000000000000000000000000000000067641b010000000000000000000000000000000000000000000000000000000000000040303030303030303038396661306666333337623938316438363638633966656636613830336635633137636236393438323864303237343430373431623234660000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000800000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000004748800000000000000000000000000000000000000000000000000000000000000e0000000000000000000000000000000000000000000000000000000000000004033303737356164316636636333633834316265366432643135313963613366623939343664646566326362613535613633326236633065366333356335353238000000000000000000000000000000000000000000000000000000000000002c303031343565343738623432623232313837313630396232363064633135346232393461303631356564346400000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000003000000000000000000000000000000000000000000000000000000000000006000000000000000000000000000000000000000000000000000000000000001a00000000000000000000000000000000000000000000000000000000000000300000000000000000000000000000000000000000000000000000000000000008000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000008d6800000000000000000000000000000000000000000000000000000000000000e0000000000000000000000000000000000000000000000000000000000000004062613136376533643661373734336237323865316336373037333465323064653366376536613765663938313964636330383663396134613535623064613536000000000000000000000000000000000000000000000000000000000000002c3030313462643537336264363863616633653131616465336531333062643036333163636133626261386632000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000800000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000003e5ec00000000000000000000000000000000000000000000000000000000000000e0000000000000000000000000000000000000000000000000000000000000004062613136376533643661373734336237323865316336373037333465323064653366376536613765663938313964636330383663396134613535623064613536000000000000000000000000000000000000000000000000000000000000004632313032366363393734623332336434363265363230353338626335313563336233613933613236666133326131336530643531313337646436346335623665316537646163000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000800000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000e0000000000000000000000000000000000000000000000000000000000000004062613136376533643661373734336237323865316336373037333465323064653366376536613765663938313964636330383663396134613535623064613536000000000000000000000000000000000000000000000000000000000000001436613038663964363538343166393436396133370000000000000000000000000000000000000000000000000000000000000000000000000000000000000042303236636339373462333233643436326536323035333862633531356333623361393361323666613332613133653064353131333764643634633562366531653764000000000000000000000000000000000000000000000000000000000000".to_string(),
+    }
+}
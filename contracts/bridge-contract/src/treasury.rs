@@ -0,0 +1,250 @@
+use crate::*;
+
+use btc::BitcoinTransactionRequest;
+use derivation::{compressed_public_key, p2wpkh_script_pubkey};
+use events::BridgeEvent;
+use near_sdk::{env, json_types::U128, near, PromiseOrValue};
+use pool::btc_outflow;
+use sign::{KeyDerivation, PATH_SEPARATOR};
+
+const BPS_DENOMINATOR: u128 = 10_000;
+
+/// Fee taken on swap payouts of a chain: `bps` of the payout, but at least `min`, in the chain's smallest unit.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeSchedule {
+    pub bps: u16,
+    #[schemars(with = "String")]
+    pub min: U128,
+}
+
+impl FeeSchedule {
+    pub fn is_free(&self) -> bool {
+        self.bps == 0 && self.min.0 == 0
+    }
+
+    pub fn fee_for(&self, payout: u128) -> u128 {
+        (payout.saturating_mul(self.bps as u128) / BPS_DENOMINATOR).max(self.min.0)
+    }
+}
+
+/// Satoshis a payout sends out of the pool, and the part of them paid to the treasury.
+pub fn btc_payout_and_fee(
+    tx_request: &BitcoinTransactionRequest,
+    pool_script_pubkey: &str,
+    treasury_script_pubkey: &str,
+) -> (u128, u128) {
+    tx_request
+        .outputs
+        .iter()
+        .filter(|output| !output.script_pubkey.eq_ignore_ascii_case(pool_script_pubkey))
+        .fold((0, 0), |(payout, fee), output| {
+            if output.script_pubkey.eq_ignore_ascii_case(treasury_script_pubkey) {
+                (payout, fee + output.value as u128)
+            } else {
+                (payout + output.value as u128, fee)
+            }
+        })
+}
+
+impl Contract {
+    /// Derivation of the treasury, namespaced under the bridge account so no caller can request it.
+    pub(crate) fn treasury_derivation() -> KeyDerivation {
+        let owner = env::current_account_id();
+        let path = format!("{}{}treasury", owner, PATH_SEPARATOR);
+        KeyDerivation::for_owner(&owner, Some(path), None)
+    }
+
+    pub(crate) fn treasury_btc_script_pubkey(&self) -> String {
+        let point = self.derived_public_key(&Self::treasury_derivation().path);
+        hex::encode(p2wpkh_script_pubkey(&point))
+    }
+
+    pub(crate) fn fee_schedule(&self, chain: Chain) -> FeeSchedule {
+        self.fee_schedules.get(&chain).cloned().unwrap_or_default()
+    }
+
+    pub(crate) fn treasury_balance(&self, chain: Chain) -> u128 {
+        self.treasury_balances.get(&chain).copied().unwrap_or(0)
+    }
+
+    /// Check that a BTC payout from the pool at `pool_script_pubkey` pays the treasury its fee,
+    /// and return the fee paid.
+    pub(crate) fn check_btc_fee(
+        &self,
+        tx_request: &BitcoinTransactionRequest,
+        pool_script_pubkey: &str,
+    ) -> Result<u128, String> {
+        let schedule = self.fee_schedule(Chain::Bitcoin);
+        if schedule.is_free() {
+            return Ok(0);
+        }
+
+        let (payout, paid) = btc_payout_and_fee(tx_request, pool_script_pubkey, &self.treasury_btc_script_pubkey());
+        let fee = schedule.fee_for(payout);
+        if paid < fee {
+            return Err(format!("Payout of {} must pay a fee of {} to the treasury, got {}", payout, fee, paid));
+        }
+        Ok(paid)
+    }
+
+    /// Hold the fee paid by the payout signed by `request_id` until the payout is finalized.
+    pub(crate) fn hold_fee(&mut self, chain: Chain, request_id: &RequestId, amount: u128) {
+        if amount > 0 {
+            self.pending_fees.insert(request_id.clone(), (chain, amount));
+        }
+    }
+
+    /// Credit the treasury with the fee of a finalized payout.
+    pub(crate) fn collect_fee(&mut self, request_id: &RequestId) {
+        let Some((chain, amount)) = self.pending_fees.remove(request_id) else {
+            return;
+        };
+
+        let balance = self.treasury_balance(chain) + amount;
+        self.treasury_balances.insert(chain, balance);
+        BridgeEvent::FeeCollected { chain, request_id: request_id.clone(), amount: U128(amount) }.emit();
+    }
+
+    /// Drop the fee of a payout that won't be signed anymore.
+    pub(crate) fn drop_fee(&mut self, request_id: &RequestId) {
+        self.pending_fees.remove(request_id);
+    }
+}
+
+#[near]
+impl Contract {
+    pub fn set_fee_schedule(&mut self, chain: Chain, schedule: FeeSchedule) {
+        self.assert_owner();
+        if schedule.bps as u128 > BPS_DENOMINATOR {
+            env::panic_str("Fee can't exceed 10000 bps");
+        }
+
+        BridgeEvent::FeeScheduleChanged { chain, bps: schedule.bps, min: schedule.min }.emit();
+        self.fee_schedules.insert(chain, schedule);
+    }
+
+    /// Sign a payout of collected fees from the treasury's derived BTC address.
+    ///
+    /// Every input must belong to the treasury, and the satoshis leaving it are debited from
+    /// the collected fees. The treasury key signs, whatever `signer_public_key` says.
+    /// Resubmitting a failed withdrawal doesn't debit the fees again.
+    #[payable]
+    pub fn withdraw_treasury_btc(&mut self, tx_request: BitcoinTransactionRequest) -> PromiseOrValue<Option<String>> {
+        self.assert_owner();
+
+        let requester = env::predecessor_account_id();
+        let derivation = Self::treasury_derivation();
+        let point = self.derived_public_key(&derivation.path);
+        let treasury_script_pubkey = hex::encode(p2wpkh_script_pubkey(&point));
+        let amount = btc_outflow(&tx_request, &treasury_script_pubkey).unwrap_or_else(|e| env::panic_str(&e));
+
+        let initial_storage_usage = env::storage_usage();
+        let prepared_bitcoin_transaction = self.prepare_btc_tx(tx_request);
+        let request_id =
+            registry::request_id(&requester, Chain::Bitcoin, &derivation, &prepared_bitcoin_transaction.sighashes);
+        if !self.requests.contains_key(&request_id) {
+            let balance = self.treasury_balance(Chain::Bitcoin);
+            if amount > balance {
+                env::panic_str(&format!("Withdrawal of {} exceeds the {} collected", amount, balance));
+            }
+            self.treasury_balances.insert(Chain::Bitcoin, balance - amount);
+            BridgeEvent::TreasuryWithdrawn { chain: Chain::Bitcoin, request_id, amount: U128(amount) }.emit();
+        }
+
+        let result = self.internal_sign_btc(
+            requester.clone(),
+            derivation,
+            prepared_bitcoin_transaction,
+            hex::encode(compressed_public_key(&point)),
+            None,
+            None,
+        );
+        self.charge_storage(&requester, initial_storage_usage);
+        result
+    }
+
+    pub fn get_fee_schedule(&self, chain: Chain) -> FeeSchedule {
+        self.fee_schedule(chain)
+    }
+
+    pub fn get_treasury_balance(&self, chain: Chain) -> U128 {
+        U128(self.treasury_balance(chain))
+    }
+
+    /// Derivation path of the treasury, to look up its addresses with `get_btc_address` and `get_evm_address`.
+    pub fn get_treasury_path(&self) -> String {
+        Self::treasury_derivation().path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use btc::{BtcInput, BtcOutput};
+    use near_sdk::{test_utils::get_logs, NearToken};
+    use sign::DEFAULT_SIGNATURE_DEPOSIT;
+    use test_utils::{btc_tx_request, register_storage, set_context, sync_test_mpc_public_key};
+
+    #[test]
+    fn test_fee_schedule() {
+        let schedule = FeeSchedule { bps: 30, min: U128(1000) };
+        assert_eq!(schedule.fee_for(100_000), 1000);
+        assert_eq!(schedule.fee_for(1_000_000), 3000);
+        assert!(FeeSchedule::default().is_free());
+
+        let tx_request = btc_tx_request(&"alice.near".parse().unwrap());
+        let pool_script_pubkey = &tx_request.inputs[0].script_pubkey;
+        let payout_script_pubkey = &tx_request.outputs[0].script_pubkey;
+        assert_eq!(btc_payout_and_fee(&tx_request, pool_script_pubkey, "00"), (tx_request.outputs[0].value as u128, 0));
+        assert_eq!(
+            btc_payout_and_fee(&tx_request, pool_script_pubkey, payout_script_pubkey),
+            (0, tx_request.outputs[0].value as u128)
+        );
+    }
+
+    #[test]
+    fn test_treasury_withdrawal() {
+        let owner: AccountId = "alice.near".parse().unwrap();
+        set_context(&owner, NearToken::from_yoctonear(0), vec![]);
+
+        let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+        register_storage(&mut contract, &owner);
+        sync_test_mpc_public_key(&mut contract);
+
+        contract.set_fee_schedule(Chain::Bitcoin, FeeSchedule { bps: 30, min: U128(1000) });
+        assert!(get_logs().iter().any(|log| log.contains("\"event\":\"fee_schedule_changed\"")));
+        assert_eq!(contract.get_fee_schedule(Chain::Evm), FeeSchedule::default());
+
+        // Fees count once their payout is finalized
+        let request_id: RequestId = "request".to_string();
+        contract.hold_fee(Chain::Bitcoin, &request_id, 1500);
+        assert_eq!(contract.get_treasury_balance(Chain::Bitcoin), U128(0));
+        contract.collect_fee(&request_id);
+        contract.collect_fee(&request_id);
+        assert_eq!(contract.get_treasury_balance(Chain::Bitcoin), U128(1500));
+
+        let treasury_script_pubkey = contract.treasury_btc_script_pubkey();
+        let withdrawal = |value: u64| BitcoinTransactionRequest {
+            inputs: vec![BtcInput {
+                txid: "8b2a3e3ec6e1f4cbe1eb8da0c2d3a44f8a6e0f3b0a3c7d5d0e1f2a3b4c5d6e7f".to_string(),
+                vout: 0,
+                value: 1500,
+                script_pubkey: treasury_script_pubkey.clone(),
+            }],
+            outputs: vec![BtcOutput { value, script_pubkey: "00140d7d0223d302b4e8ef37050b5200b1c3306ae7ab".to_string() }],
+            signer_public_key: String::new(),
+        };
+
+        set_context(&owner, DEFAULT_SIGNATURE_DEPOSIT, vec![]);
+        contract.withdraw_treasury_btc(withdrawal(1400));
+        assert!(get_logs().iter().any(|log| log.contains("\"event\":\"treasury_withdrawn\"")));
+        assert_eq!(contract.get_treasury_balance(Chain::Bitcoin), U128(0));
+
+        // Only collected fees can be withdrawn
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.withdraw_treasury_btc(withdrawal(1300))
+        }));
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,228 @@
+use crate::*;
+
+use events::BridgeEvent;
+use near_sdk::{env, json_types::Base64VecU8, near, Gas, IntoStorageKey, NearToken, Promise};
+
+/// Default time between staging an upgrade and being able to deploy it.
+pub const DEFAULT_UPGRADE_DELAY_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// Gas left to the `migrate` call chained after deploying the staged code.
+const MIGRATE_GAS: Gas = Gas::from_tgas(100);
+
+/// Code waiting for its delay to pass. The wasm itself is stored under `StorageKey::StagedCode`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StagedUpgrade {
+    /// Hex-encoded sha256 of the staged wasm.
+    pub code_hash: String,
+    pub staged_at: u64,
+    pub deployable_at: u64,
+}
+
+/// Shorter delay set by the owner, which only applies once the delay it replaces has passed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema, BorshSerialize, BorshDeserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingUpgradeDelay {
+    pub upgrade_delay_ns: u64,
+    pub effective_at: u64,
+}
+
+fn staged_code_key() -> Vec<u8> {
+    StorageKey::StagedCode.into_storage_key()
+}
+
+impl Contract {
+    /// Delay currently in force, including a decrease whose wait is over.
+    fn upgrade_delay(&self) -> u64 {
+        match &self.pending_upgrade_delay {
+            Some(pending) if env::block_timestamp() >= pending.effective_at => pending.upgrade_delay_ns,
+            _ => self.upgrade_delay_ns,
+        }
+    }
+
+    fn apply_pending_upgrade_delay(&mut self) {
+        let upgrade_delay_ns = self.upgrade_delay();
+        if upgrade_delay_ns != self.upgrade_delay_ns {
+            self.upgrade_delay_ns = upgrade_delay_ns;
+            self.pending_upgrade_delay = None;
+        }
+    }
+}
+
+#[near]
+impl Contract {
+    /// Store `code` for deployment once `upgrade_delay_ns` has passed, replacing any staged upgrade.
+    pub fn stage_upgrade(&mut self, code: Base64VecU8) -> StagedUpgrade {
+        self.assert_owner();
+
+        let code: Vec<u8> = code.into();
+        if code.is_empty() {
+            env::panic_str("Upgrade code is empty");
+        }
+
+        self.apply_pending_upgrade_delay();
+        let now = env::block_timestamp();
+        let staged_upgrade = StagedUpgrade {
+            code_hash: hex::encode(env::sha256_array(&code)),
+            staged_at: now,
+            deployable_at: now.saturating_add(self.upgrade_delay_ns),
+        };
+
+        env::storage_write(&staged_code_key(), &code);
+        self.staged_upgrade = Some(staged_upgrade.clone());
+        BridgeEvent::UpgradeStaged {
+            code_hash: staged_upgrade.code_hash.clone(),
+            deployable_at: staged_upgrade.deployable_at,
+        }
+        .emit();
+
+        staged_upgrade
+    }
+
+    pub fn cancel_upgrade(&mut self) {
+        self.assert_owner();
+
+        let staged_upgrade = self
+            .staged_upgrade
+            .take()
+            .unwrap_or_else(|| env::panic_str("No upgrade is staged"));
+        env::storage_remove(&staged_code_key());
+
+        BridgeEvent::UpgradeCancelled { code_hash: staged_upgrade.code_hash }.emit();
+    }
+
+    /// Deploy the staged code and migrate the state in the same batch, so a failing migration
+    /// also reverts the deployment.
+    pub fn deploy_upgrade(&mut self) -> Promise {
+        self.assert_owner();
+
+        let staged_upgrade = self
+            .staged_upgrade
+            .clone()
+            .unwrap_or_else(|| env::panic_str("No upgrade is staged"));
+        if env::block_timestamp() < staged_upgrade.deployable_at {
+            env::panic_str(&format!("Upgrade can't be deployed before {}", staged_upgrade.deployable_at));
+        }
+        self.staged_upgrade = None;
+
+        let code = env::storage_read(&staged_code_key()).unwrap_or_else(|| env::panic_str("Staged code is missing"));
+        env::storage_remove(&staged_code_key());
+        BridgeEvent::UpgradeDeployed { code_hash: staged_upgrade.code_hash }.emit();
+
+        Promise::new(env::current_account_id())
+            .deploy_contract(code)
+            .function_call("migrate".to_string(), Vec::new(), NearToken::from_yoctonear(0), MIGRATE_GAS)
+    }
+
+    /// Longer delays apply at once. Shorter ones only apply once the current delay has passed, so
+    /// lowering the delay can't be used to deploy an upgrade sooner than the current delay allows.
+    pub fn set_upgrade_delay(&mut self, upgrade_delay_ns: u64) {
+        self.assert_owner();
+        self.apply_pending_upgrade_delay();
+
+        if upgrade_delay_ns >= self.upgrade_delay_ns {
+            self.upgrade_delay_ns = upgrade_delay_ns;
+            self.pending_upgrade_delay = None;
+            BridgeEvent::UpgradeDelayChanged { upgrade_delay_ns }.emit();
+        } else {
+            let effective_at = env::block_timestamp().saturating_add(self.upgrade_delay_ns);
+            self.pending_upgrade_delay = Some(PendingUpgradeDelay { upgrade_delay_ns, effective_at });
+            BridgeEvent::UpgradeDelayDecreaseStaged { upgrade_delay_ns, effective_at }.emit();
+        }
+    }
+
+    pub fn get_upgrade_delay(&self) -> u64 {
+        self.upgrade_delay()
+    }
+
+    /// Shorter delay waiting for the current one to pass, if any.
+    pub fn get_pending_upgrade_delay(&self) -> Option<PendingUpgradeDelay> {
+        self.pending_upgrade_delay
+            .clone()
+            .filter(|pending| env::block_timestamp() < pending.effective_at)
+    }
+
+    pub fn get_staged_upgrade(&self) -> Option<StagedUpgrade> {
+        self.staged_upgrade.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::{test_utils::VMContextBuilder, testing_env};
+
+    fn set_owner_context(block_timestamp: u64) {
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id("alice.near".parse().unwrap())
+            .block_timestamp(block_timestamp)
+            .build());
+    }
+
+    #[test]
+    fn test_staged_upgrade() {
+        set_owner_context(0);
+        let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+
+        let code = vec![0, 97, 115, 109];
+        let staged_upgrade = contract.stage_upgrade(code.clone().into());
+        assert_eq!(staged_upgrade.code_hash, hex::encode(env::sha256(&code)));
+        assert_eq!(staged_upgrade.deployable_at, DEFAULT_UPGRADE_DELAY_NS);
+        assert_eq!(contract.get_staged_upgrade(), Some(staged_upgrade));
+
+        // Too early
+        set_owner_context(DEFAULT_UPGRADE_DELAY_NS - 1);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| contract.deploy_upgrade()));
+        assert!(result.is_err());
+
+        set_owner_context(DEFAULT_UPGRADE_DELAY_NS);
+        contract.deploy_upgrade();
+        assert_eq!(contract.get_staged_upgrade(), None);
+        assert!(!env::storage_has_key(&staged_code_key()));
+    }
+
+    #[test]
+    fn test_cancel_upgrade() {
+        set_owner_context(0);
+        let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+
+        contract.stage_upgrade(vec![0, 97, 115, 109].into());
+        contract.cancel_upgrade();
+        assert_eq!(contract.get_staged_upgrade(), None);
+
+        set_owner_context(DEFAULT_UPGRADE_DELAY_NS);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| contract.deploy_upgrade()));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_upgrade_delay_decrease_waits_for_current_delay() {
+        set_owner_context(0);
+        let mut contract = Contract::new("v1.signer-prod.testnet".parse().unwrap());
+
+        // Lowering the delay doesn't let an upgrade staged right after skip the current delay
+        contract.set_upgrade_delay(0);
+        assert_eq!(contract.get_upgrade_delay(), DEFAULT_UPGRADE_DELAY_NS);
+        assert_eq!(
+            contract.get_pending_upgrade_delay(),
+            Some(PendingUpgradeDelay { upgrade_delay_ns: 0, effective_at: DEFAULT_UPGRADE_DELAY_NS })
+        );
+        let staged_upgrade = contract.stage_upgrade(vec![0, 97, 115, 109].into());
+        assert_eq!(staged_upgrade.deployable_at, DEFAULT_UPGRADE_DELAY_NS);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| contract.deploy_upgrade()));
+        assert!(result.is_err());
+
+        // Once the current delay has passed, the lower one applies
+        set_owner_context(DEFAULT_UPGRADE_DELAY_NS);
+        assert_eq!(contract.get_upgrade_delay(), 0);
+        assert_eq!(contract.get_pending_upgrade_delay(), None);
+        contract.stage_upgrade(vec![0, 97, 115, 109].into());
+        contract.deploy_upgrade();
+
+        // Raising the delay applies at once and drops a pending decrease
+        contract.set_upgrade_delay(10);
+        contract.set_upgrade_delay(2 * DEFAULT_UPGRADE_DELAY_NS);
+        assert_eq!(contract.get_upgrade_delay(), 2 * DEFAULT_UPGRADE_DELAY_NS);
+        assert_eq!(contract.get_pending_upgrade_delay(), None);
+    }
+}
@@ -0,0 +1,256 @@
+use crate::*;
+
+use k256::{
+    ecdsa::{RecoveryId, Signature as K256Signature, VerifyingKey},
+    elliptic_curve::{scalar::IsHigh, sec1::ToEncodedPoint, PrimeField},
+    AffinePoint, FieldBytes, Scalar,
+};
+use near_sdk::{env, serde::{Deserialize, Serialize}, FunctionError};
+use schemars::JsonSchema;
+use signer::{SerializableScalar, SignResult};
+use std::fmt;
+
+/// Reasons a signature returned by the signer can't be used to finalize a transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "near_sdk::serde", tag = "kind", rename_all = "snake_case")]
+pub enum SignatureError {
+    /// `big_r`, `s` or `recovery_id` could not be decoded.
+    Malformed { reason: String },
+    /// No public key could be recovered for the payload.
+    RecoveryFailed { reason: String },
+    /// The recovered key is not the one that should have signed.
+    PublicKeyMismatch { expected: String, recovered: String },
+    /// The recovered key does not control the expected EVM address.
+    AddressMismatch { expected: String, recovered: String },
+    /// The number of signatures does not match the number of payloads.
+    CountMismatch { expected: u64, actual: u64 },
+    /// The ed25519 signature does not verify under the transaction's signer key.
+    Ed25519VerificationFailed { public_key: String },
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::Malformed { reason } => write!(f, "Malformed signature: {}", reason),
+            SignatureError::RecoveryFailed { reason } => write!(f, "Failed to recover public key: {}", reason),
+            SignatureError::PublicKeyMismatch { expected, recovered } => write!(
+                f,
+                "Signature was produced by {} instead of {}",
+                recovered, expected
+            ),
+            SignatureError::AddressMismatch { expected, recovered } => write!(
+                f,
+                "Signature recovers to address {} instead of {}",
+                recovered, expected
+            ),
+            SignatureError::CountMismatch { expected, actual } => write!(
+                f,
+                "Expected {} signatures, got {}",
+                expected, actual
+            ),
+            SignatureError::Ed25519VerificationFailed { public_key } => write!(
+                f,
+                "Ed25519 signature does not verify under {}",
+                public_key
+            ),
+        }
+    }
+}
+
+impl FunctionError for SignatureError {
+    fn panic(&self) -> ! {
+        env::panic_str(&self.to_string())
+    }
+}
+
+fn malformed(reason: impl Into<String>) -> SignatureError {
+    SignatureError::Malformed { reason: reason.into() }
+}
+
+/// Decode the signer's response into a k256 signature and recovery id.
+///
+/// `big_r` is the compressed R point, only its x-coordinate is part of the signature.
+pub fn parse_sign_result(signature: &SignResult) -> Result<(K256Signature, RecoveryId), SignatureError> {
+    let big_r = hex::decode(&signature.big_r.affine_point).map_err(|_| malformed("big_r is not hex"))?;
+    if big_r.len() != 33 {
+        return Err(malformed("big_r must be a 33-byte compressed point"));
+    }
+
+    let s = hex::decode(&signature.s.scalar).map_err(|_| malformed("s is not hex"))?;
+    if s.len() != 32 {
+        return Err(malformed("s must be 32 bytes"));
+    }
+
+    let k256_signature = K256Signature::from_scalars(
+        *FieldBytes::from_slice(&big_r[1..]),
+        *FieldBytes::from_slice(&s),
+    )
+    .map_err(|_| malformed("r or s is out of range"))?;
+
+    let recovery_id = RecoveryId::from_byte(signature.recovery_id)
+        .ok_or_else(|| malformed("recovery_id must be between 0 and 3"))?;
+
+    Ok((k256_signature, recovery_id))
+}
+
+/// Canonicalize `signature` to its low-S form, as required by BTC standardness and EVM.
+///
+/// Negating S mirrors R's y-coordinate, so the recovery id parity flips with it.
+pub fn normalize_s(signature: SignResult) -> Result<SignResult, SignatureError> {
+    let s_bytes = hex::decode(&signature.s.scalar).map_err(|_| malformed("s is not hex"))?;
+    if s_bytes.len() != 32 {
+        return Err(malformed("s must be 32 bytes"));
+    }
+
+    let s = Option::<Scalar>::from(Scalar::from_repr(*FieldBytes::from_slice(&s_bytes)))
+        .ok_or_else(|| malformed("s is out of range"))?;
+
+    if !bool::from(s.is_high()) {
+        return Ok(signature);
+    }
+
+    Ok(SignResult {
+        big_r: signature.big_r,
+        s: SerializableScalar {
+            scalar: hex::encode_upper((-s).to_bytes()),
+        },
+        recovery_id: signature.recovery_id ^ 1,
+    })
+}
+
+/// Recover the public key that produced `signature` over the prehashed `payload`.
+pub fn recover_public_key(payload: &[u8; 32], signature: &SignResult) -> Result<AffinePoint, SignatureError> {
+    let (k256_signature, recovery_id) = parse_sign_result(signature)?;
+
+    VerifyingKey::recover_from_prehash(payload, &k256_signature, recovery_id)
+        .map(|key| *key.as_affine())
+        .map_err(|e| SignatureError::RecoveryFailed { reason: e.to_string() })
+}
+
+/// Check that `signature` over `payload` was produced by `expected`.
+pub fn verify_signature(
+    payload: &[u8; 32],
+    signature: &SignResult,
+    expected: &AffinePoint,
+) -> Result<(), SignatureError> {
+    let recovered = recover_public_key(payload, signature)?;
+
+    if recovered != *expected {
+        return Err(SignatureError::PublicKeyMismatch {
+            expected: hex::encode(expected.to_encoded_point(true).as_bytes()),
+            recovered: hex::encode(recovered.to_encoded_point(true).as_bytes()),
+        });
+    }
+
+    Ok(())
+}
+
+/// Check that `signature` over `payload` was produced by the owner of `expected_address`.
+pub fn verify_evm_signature(
+    payload: &[u8; 32],
+    signature: &SignResult,
+    expected_address: &[u8; 20],
+) -> Result<(), SignatureError> {
+    let recovered = derivation::evm_address_bytes(&recover_public_key(payload, signature)?);
+
+    if recovered != *expected_address {
+        return Err(SignatureError::AddressMismatch {
+            expected: derivation::to_checksum_address(expected_address),
+            recovered: derivation::to_checksum_address(&recovered),
+        });
+    }
+
+    Ok(())
+}
+
+/// Parse a hex-encoded SEC1 public key, as used in BTC witnesses.
+pub fn parse_public_key_hex(public_key: &str) -> Result<AffinePoint, SignatureError> {
+    let bytes = hex::decode(public_key).map_err(|_| malformed("public key is not hex"))?;
+
+    k256::PublicKey::from_sec1_bytes(&bytes)
+        .map(|key| *key.as_affine())
+        .map_err(|_| malformed("public key is not a valid secp256k1 point"))
+}
+
+/// Parse a `0x`-prefixed or bare hex EVM address.
+pub fn parse_evm_address_hex(address: &str) -> Result<[u8; 20], SignatureError> {
+    let bytes = hex::decode(address.trim_start_matches("0x")).map_err(|_| malformed("address is not hex"))?;
+
+    bytes.try_into().map_err(|_| malformed("address must be 20 bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use signer::SerializableAffinePoint;
+
+    fn btc_test_signature() -> SignResult {
+        SignResult {
+            big_r: SerializableAffinePoint {
+                affine_point: "03E123DAC9EA85FF349A301BD6591657F1ED8A0D349F226080D624022284F4D193".to_string(),
+            },
+            s: SerializableScalar {
+                scalar: "689983EFBBF85DF34A99507DF24077BA85C92FCB54146D554F55B60A1626A816".to_string(),
+            },
+            recovery_id: 0,
+        }
+    }
+
+    /// `btc_test_signature` with S negated, as a signer without low-S normalization may return it.
+    fn btc_test_signature_high_s() -> SignResult {
+        SignResult {
+            big_r: SerializableAffinePoint {
+                affine_point: "03E123DAC9EA85FF349A301BD6591657F1ED8A0D349F226080D624022284F4D193".to_string(),
+            },
+            s: SerializableScalar {
+                scalar: "97667C104407A20CB566AF820DBF884434E5AD1B5B3432E6707CA882BA0F992B".to_string(),
+            },
+            recovery_id: 1,
+        }
+    }
+
+    const BTC_TEST_SIGHASH: [u8; 32] = [224, 73, 126, 48, 217, 94, 79, 58, 71, 74, 219, 119, 243, 197, 183, 197, 103, 2, 227, 119, 154, 47, 20, 175, 240, 168, 89, 60, 152, 92, 190, 186];
+
+    #[test]
+    fn test_verify_signature() {
+        let expected = parse_public_key_hex("02b12224ecec8184dbff10316a889ebee9f7871bd6de358c5323fbecce9d84fd24").unwrap();
+        assert_eq!(verify_signature(&BTC_TEST_SIGHASH, &btc_test_signature(), &expected), Ok(()));
+
+        let mut other_payload = BTC_TEST_SIGHASH;
+        other_payload[0] ^= 1;
+        assert!(matches!(
+            verify_signature(&other_payload, &btc_test_signature(), &expected),
+            Err(SignatureError::PublicKeyMismatch { .. })
+        ));
+
+        let mut wrong_recovery_id = btc_test_signature();
+        wrong_recovery_id.recovery_id = 1;
+        assert!(verify_signature(&BTC_TEST_SIGHASH, &wrong_recovery_id, &expected).is_err());
+
+        let mut truncated = btc_test_signature();
+        truncated.s.scalar.truncate(10);
+        assert!(matches!(
+            verify_signature(&BTC_TEST_SIGHASH, &truncated, &expected),
+            Err(SignatureError::Malformed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_normalize_s() {
+        let low_s = btc_test_signature();
+        let normalized = normalize_s(low_s.clone()).unwrap();
+        assert_eq!(normalized.s.scalar, low_s.s.scalar);
+        assert_eq!(normalized.recovery_id, low_s.recovery_id);
+
+        let normalized = normalize_s(btc_test_signature_high_s()).unwrap();
+        assert_eq!(normalized.s.scalar, low_s.s.scalar);
+        assert_eq!(normalized.recovery_id, low_s.recovery_id);
+
+        let expected = parse_public_key_hex("02b12224ecec8184dbff10316a889ebee9f7871bd6de358c5323fbecce9d84fd24").unwrap();
+        assert_eq!(verify_signature(&BTC_TEST_SIGHASH, &normalized, &expected), Ok(()));
+
+        let mut out_of_range = btc_test_signature();
+        out_of_range.s.scalar = "FF".repeat(32);
+        assert!(matches!(normalize_s(out_of_range), Err(SignatureError::Malformed { .. })));
+    }
+}